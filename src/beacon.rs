@@ -0,0 +1,160 @@
+// WAN reachability for shard placement: an IGD/UPnP port mapping so a NATed
+// node has a publicly reachable address, plus a simple out-of-band beacon
+// that lets two operators exchange that reachability information without a
+// rendezvous server - by dropping it in a shared file or piping it through
+// whatever command they already have (a pastebin CLI, scp, a chat webhook).
+
+use std::fs;
+use std::net::SocketAddr;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use x25519_dalek::PublicKey;
+
+use crate::handshake::TrustedPeers;
+use crate::{Device, Result};
+
+/// Attempts to map `LISTEN_PORT` through an IGD/UPnP-capable gateway and
+/// returns the externally reachable address on success. Absence of a
+/// gateway, or a gateway that refuses the mapping, is not an error - WAN
+/// placement is best-effort and mDNS remains the zero-config LAN fallback.
+pub fn map_external_port(port: u16) -> Option<SocketAddr> {
+    let gateway = match igd::search_gateway(igd::SearchOptions::default()) {
+        Ok(gw) => gw,
+        Err(e) => {
+            println!("UPnP gateway discovery failed: {}", e);
+            return None;
+        }
+    };
+
+    let local_ip = match local_ip_address::local_ip() {
+        Ok(ip) => ip,
+        Err(_) => return None,
+    };
+    let local_addr = SocketAddr::new(local_ip, port);
+
+    match gateway.add_port(
+        igd::PortMappingProtocol::TCP,
+        port,
+        local_addr,
+        3600,
+        "vishwarupa shard transport",
+    ) {
+        Ok(()) => match gateway.get_external_ip() {
+            Ok(ext_ip) => {
+                println!("UPnP mapped external {}:{} -> local {}", ext_ip, port, local_addr);
+                Some(SocketAddr::new(ext_ip, port))
+            }
+            Err(e) => {
+                println!("UPnP mapping succeeded but external IP lookup failed: {}", e);
+                None
+            }
+        },
+        Err(e) => {
+            println!("UPnP port mapping failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Everything a peer needs to dial this node from anywhere: its device id,
+/// static public key (for the handshake's trust check), and every address
+/// it might be reachable on (LAN, UPnP-mapped WAN, whatever else).
+#[derive(Serialize, Deserialize)]
+struct BeaconInfo {
+    device_id: String,
+    static_public_key: String,
+    addresses: Vec<String>,
+}
+
+/// Encodes this node's reachability into a short opaque string suitable for
+/// pasting into a file or a chat message: base64 of a small JSON payload.
+pub fn encode_beacon(device_id: &str, static_public_key_hex: &str, addresses: &[SocketAddr]) -> Result<String> {
+    let info = BeaconInfo {
+        device_id: device_id.to_string(),
+        static_public_key: static_public_key_hex.to_string(),
+        addresses: addresses.iter().map(|a| a.to_string()).collect(),
+    };
+    let json = serde_json::to_vec(&info)?;
+    Ok(base64::encode(json))
+}
+
+fn decode_beacon(encoded: &str) -> Result<BeaconInfo> {
+    let json = base64::decode(encoded.trim())?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Turns a decoded beacon into devices, folding its static key into
+/// `trusted` along the way - under explicit-trust mode an address-only
+/// beacon peer would otherwise always fail the handshake's `trusts()`
+/// check, since that mode's trust set is never populated by address-based
+/// discovery, only by a `trusted_keys` entry or another beacon like this one.
+fn beacon_to_devices(info: BeaconInfo, trusted: &TrustedPeers) -> Result<Vec<Device>> {
+    let key_bytes = hex::decode(info.static_public_key.trim())?;
+    if key_bytes.len() != 32 {
+        return Err(format!("Invalid beacon static key for {}", info.device_id).into());
+    }
+    let mut raw = [0u8; 32];
+    raw.copy_from_slice(&key_bytes);
+    trusted.insert(PublicKey::from(raw));
+
+    Ok(info.addresses
+        .iter()
+        .filter_map(|addr| addr.parse::<SocketAddr>().ok())
+        .map(|addr| Device {
+            device_id: info.device_id.clone(),
+            device_type: "beacon".to_string(),
+            address: addr.ip().to_string(),
+            port: addr.port(),
+        })
+        .collect())
+}
+
+/// Writes this node's beacon to a shared file path, e.g. a synced folder or
+/// a path on a host both operators can reach.
+pub fn write_beacon_file(path: &str, encoded: &str) -> Result<()> {
+    fs::write(path, encoded)?;
+    Ok(())
+}
+
+/// Reads a peer's beacon back out of a shared file path.
+pub fn read_beacon_file(path: &str, trusted: &TrustedPeers) -> Result<Vec<Device>> {
+    let encoded = fs::read_to_string(path)?;
+    beacon_to_devices(decode_beacon(&encoded)?, trusted)
+}
+
+/// Runs a configured shell command and treats its stdout as a peer's beacon.
+/// This covers anything a file drop can't: posting to a webhook, reading
+/// from a pastebin, querying a custom rendezvous script the operator wrote.
+pub fn run_beacon_command(command: &str, trusted: &TrustedPeers) -> Result<Vec<Device>> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if !output.status.success() {
+        return Err(format!("Beacon command exited with {}", output.status).into());
+    }
+    let encoded = String::from_utf8_lossy(&output.stdout);
+    beacon_to_devices(decode_beacon(&encoded)?, trusted)
+}
+
+/// Pulls in whatever beacon sources are configured via environment variables
+/// (`BEACON_FILE`, `BEACON_COMMAND`), logging and skipping any that fail
+/// instead of aborting discovery, and folding each peer's static key into
+/// `trusted` so explicit-trust mode can actually dial them.
+pub fn discover_beacon_peers(trusted: &TrustedPeers) -> Vec<Device> {
+    let mut peers = Vec::new();
+
+    if let Ok(path) = std::env::var("BEACON_FILE") {
+        match read_beacon_file(&path, trusted) {
+            Ok(mut devices) => peers.append(&mut devices),
+            Err(e) => println!("Beacon file discovery failed: {}", e),
+        }
+    }
+
+    if let Ok(command) = std::env::var("BEACON_COMMAND") {
+        match run_beacon_command(&command, trusted) {
+            Ok(mut devices) => peers.append(&mut devices),
+            Err(e) => println!("Beacon command discovery failed: {}", e),
+        }
+    }
+
+    peers
+}
@@ -0,0 +1,262 @@
+// Kademlia-style routing table and iterative lookups, used so `download` can
+// locate which peers hold a chunk by content hash instead of only asking
+// flat mDNS neighbours. Node and content ids are 256-bit (BLAKE3 of the
+// device id / file id), distance is XOR, and the routing table is split into
+// one k-bucket per bit-prefix, each holding up to `K` peers ordered
+// least-recently-seen first.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tokio::net::TcpStream;
+
+use crate::handshake::SecureStream;
+use crate::{protocol, Device, NodeContext, Result};
+
+pub const K: usize = 20;
+pub const ALPHA: usize = 3;
+const ID_BITS: usize = 256;
+
+pub type NodeId = [u8; 32];
+
+pub fn node_id_for_device(device_id: &str) -> NodeId {
+    *blake3::hash(device_id.as_bytes()).as_bytes()
+}
+
+pub fn content_id(file_id: &str) -> NodeId {
+    *blake3::hash(file_id.as_bytes()).as_bytes()
+}
+
+fn xor_distance(a: &NodeId, b: &NodeId) -> NodeId {
+    let mut d = [0u8; 32];
+    for i in 0..32 {
+        d[i] = a[i] ^ b[i];
+    }
+    d
+}
+
+/// Index of the k-bucket that should hold a peer at this distance: the
+/// position of its highest set bit, counting up from the least significant
+/// bit, so bucket `i` holds peers at distances in `[2^i, 2^(i+1))`.
+fn bucket_index(distance: &NodeId) -> usize {
+    for (byte_idx, byte) in distance.iter().enumerate() {
+        if *byte != 0 {
+            let leading = byte.leading_zeros() as usize;
+            return ID_BITS - (byte_idx * 8 + leading) - 1;
+        }
+    }
+    0
+}
+
+#[derive(Clone)]
+pub struct PeerEntry {
+    pub id: NodeId,
+    pub device: Device,
+}
+
+struct KBucket {
+    // Front = least-recently-seen, back = most-recently-seen.
+    entries: VecDeque<PeerEntry>,
+}
+
+impl KBucket {
+    fn new() -> Self {
+        Self { entries: VecDeque::new() }
+    }
+
+    fn touch(&mut self, entry: PeerEntry) {
+        if let Some(pos) = self.entries.iter().position(|e| e.id == entry.id) {
+            self.entries.remove(pos);
+        } else if self.entries.len() >= K {
+            // A full bucket evicts its least-recently-seen entry. Classic
+            // Kademlia pings that peer first and only evicts if it doesn't
+            // answer; we keep this simpler LRU eviction for now.
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+/// This node's view of the network: its own id plus one bucket per bit of
+/// the id space.
+pub struct RoutingTable {
+    self_id: NodeId,
+    buckets: Mutex<Vec<KBucket>>,
+}
+
+impl RoutingTable {
+    pub fn new(self_id: NodeId) -> Self {
+        Self {
+            self_id,
+            buckets: Mutex::new((0..ID_BITS).map(|_| KBucket::new()).collect()),
+        }
+    }
+
+    pub fn insert(&self, id: NodeId, device: Device) {
+        if id == self.self_id {
+            return;
+        }
+        let idx = bucket_index(&xor_distance(&self.self_id, &id));
+        self.buckets.lock().unwrap()[idx].touch(PeerEntry { id, device });
+    }
+
+    /// The `count` known peers closest to `target`, nearest first.
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<PeerEntry> {
+        let mut all = self.all_peers();
+        all.sort_by_key(|e| xor_distance(&e.id, target));
+        all.truncate(count);
+        all
+    }
+
+    pub fn all_peers(&self) -> Vec<PeerEntry> {
+        self.buckets.lock().unwrap().iter().flat_map(|b| b.entries.iter().cloned()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap().iter().map(|b| b.entries.len()).sum()
+    }
+}
+
+/// Ranks an arbitrary device list by XOR closeness to `target`. Unlike
+/// `RoutingTable::closest`, this doesn't require the devices to already be
+/// registered in the table - useful for one-off placement decisions (e.g.
+/// picking which `R` live peers a chunk's replicas go to) over whatever
+/// devices discovery just returned.
+pub fn rank_by_closeness(devices: &[Device], target: NodeId) -> Vec<Device> {
+    let mut ranked: Vec<Device> = devices.to_vec();
+    ranked.sort_by_key(|d| xor_distance(&node_id_for_device(&d.device_id), &target));
+    ranked
+}
+
+/// Opens a fresh authenticated connection to `device`, sends one framed
+/// request, and returns the framed response. DHT RPCs are one-shot - a new
+/// connection and handshake per request, as in `send_shard` - since lookups
+/// fan out to many peers that are each contacted once.
+async fn rpc(ctx: &NodeContext, device: &Device, request: Vec<u8>) -> Result<Vec<u8>> {
+    let addr = format!("{}:{}", device.address, device.port);
+    let stream = tokio::time::timeout(
+        tokio::time::Duration::from_secs(3),
+        TcpStream::connect(&addr),
+    ).await.map_err(|_| "Connection timeout")??;
+
+    let mut secure = tokio::time::timeout(
+        tokio::time::Duration::from_secs(5),
+        SecureStream::initiate(stream, &ctx.identity, &ctx.trusted),
+    ).await.map_err(|_| "Handshake timeout")??;
+
+    secure.send_frame(&request).await?;
+    secure.recv_frame().await
+}
+
+async fn find_node_rpc(ctx: &NodeContext, device: &Device, target: NodeId) -> Result<Vec<PeerInfo>> {
+    let response = rpc(ctx, device, protocol::encode_find_node(target)?).await?;
+    let (parsed, _): (protocol::FindNodeResponse, Vec<u8>) = protocol::decode_response(&response)?;
+    Ok(parsed.peers.into_iter().map(|p| PeerEntry { id: p.id, device: p.device }).collect())
+}
+
+type PeerInfo = PeerEntry;
+
+/// Iterative `FIND_NODE`: repeatedly asks the `ALPHA` closest unqueried
+/// peers known so far about `target`, folding their answers into the
+/// candidate set, until the closest `K` peers stop improving.
+pub async fn iterative_find_node(ctx: &NodeContext, table: &RoutingTable, target: NodeId) -> Vec<PeerEntry> {
+    let mut queried = std::collections::HashSet::new();
+    let mut candidates = table.closest(&target, K);
+
+    loop {
+        let to_query: Vec<_> = candidates
+            .iter()
+            .filter(|c| !queried.contains(&c.id))
+            .take(ALPHA)
+            .cloned()
+            .collect();
+        if to_query.is_empty() {
+            break;
+        }
+
+        let mut discovered = Vec::new();
+        for peer in &to_query {
+            queried.insert(peer.id);
+            if let Ok(found) = find_node_rpc(ctx, &peer.device, target).await {
+                for f in &found {
+                    table.insert(f.id, f.device.clone());
+                }
+                discovered.extend(found);
+            }
+        }
+
+        let before = candidates.first().map(|c| xor_distance(&c.id, &target));
+        candidates.extend(discovered);
+        candidates.sort_by_key(|e| xor_distance(&e.id, &target));
+        candidates.dedup_by_key(|e| e.id);
+        candidates.truncate(K);
+        let after = candidates.first().map(|c| xor_distance(&c.id, &target));
+
+        if before == after && to_query.iter().all(|c| queried.contains(&c.id)) {
+            // No improvement from this round and everyone queryable has
+            // been queried - the classic Kademlia termination condition.
+            if to_query.len() < ALPHA {
+                break;
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Iterative `FIND_VALUE`: like `iterative_find_node`, but a peer can short
+/// circuit the search by returning providers directly instead of more peers.
+pub async fn iterative_find_value(ctx: &NodeContext, table: &RoutingTable, key: NodeId) -> Option<Vec<Device>> {
+    let mut queried = std::collections::HashSet::new();
+    let mut candidates = table.closest(&key, K);
+
+    loop {
+        let to_query: Vec<_> = candidates
+            .iter()
+            .filter(|c| !queried.contains(&c.id))
+            .take(ALPHA)
+            .cloned()
+            .collect();
+        if to_query.is_empty() {
+            return None;
+        }
+
+        for peer in &to_query {
+            queried.insert(peer.id);
+            let response = match rpc(ctx, &peer.device, protocol::encode_find_value(key).ok()?).await {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let (parsed, _): (protocol::FindValueResponse, Vec<u8>) = match protocol::decode_response(&response) {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+            match parsed {
+                protocol::FindValueResponse::Value(providers) => return Some(providers),
+                protocol::FindValueResponse::Peers(peers) => {
+                    for p in peers {
+                        table.insert(p.id, p.device.clone());
+                        candidates.push(PeerEntry { id: p.id, device: p.device });
+                    }
+                }
+            }
+        }
+
+        candidates.sort_by_key(|e| xor_distance(&e.id, &key));
+        candidates.dedup_by_key(|e| e.id);
+        candidates.truncate(K);
+    }
+}
+
+/// Publishes the fact that `providers` hold content addressed by `key` to
+/// the closest known peers, so a later `locate` can find them without
+/// already knowing which devices were involved in the original upload.
+pub async fn store_providers(ctx: &NodeContext, table: &RoutingTable, key: NodeId, providers: Vec<Device>) {
+    for peer in table.closest(&key, K) {
+        let request = match protocol::encode_store_providers(key, providers.clone()) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        let _ = rpc(ctx, &peer.device, request).await;
+    }
+}
@@ -0,0 +1,529 @@
+// Mutually-authenticated, encrypted transport wrapped around every TcpStream
+// before the GET/PUT shard protocol runs. Loosely modelled on Noise: the
+// initiator sends only its ephemeral public key, the responder replies with
+// its own ephemeral key plus its static key sealed under the
+// ephemeral-ephemeral DH, and the initiator then seals its own static key
+// under that same ephemeral-ephemeral DH before either side's identity is
+// ever sent in the clear. Both sides mix DH(e_i,e_r), DH(e_i,s_r) and
+// DH(s_i,e_r) through HKDF-SHA256 into a pair of directional ChaCha20Poly1305
+// session keys. Each side aborts the connection unless the peer's revealed
+// static key is in its trusted set.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chacha20poly1305::{aead::{Aead, KeyInit}, ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::Result;
+
+const HANDSHAKE_INFO: &[u8] = b"vishwarupa handshake v1";
+const EPHEMERAL_INFO: &[u8] = b"vishwarupa ephemeral v1";
+
+// Distinct nonces for the two static-key-sealing messages carried under the
+// same ephemeral-ephemeral key (responder's flight, then initiator's) -
+// must differ so encrypting two different messages under one key never
+// reuses a (key, nonce) pair.
+const RESPONDER_STATIC_NONCE: [u8; 12] = [0u8; 12];
+const INITIATOR_STATIC_NONCE: [u8; 12] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+/// A node's long-term X25519 identity, persisted next to `device_id_<port>.txt`.
+pub struct StaticIdentity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl StaticIdentity {
+    /// Loads the static keypair for this port, generating and persisting one on first run.
+    pub fn load_or_generate(port: u16) -> Result<Self> {
+        let key_file = format!("static_key_{}.hex", port);
+        if Path::new(&key_file).exists() {
+            return Self::from_hex_file(&key_file);
+        }
+
+        let secret = StaticSecret::random_from_rng(OsRng);
+        fs::write(&key_file, hex::encode(secret.to_bytes()))?;
+        let public = PublicKey::from(&secret);
+        Ok(Self { secret, public })
+    }
+
+    fn from_hex_file(key_file: &str) -> Result<Self> {
+        let hex_str = fs::read_to_string(key_file)?;
+        let bytes = hex::decode(hex_str.trim())?;
+        if bytes.len() != 32 {
+            return Err("Invalid static key file".into());
+        }
+        let mut raw = [0u8; 32];
+        raw.copy_from_slice(&bytes);
+        let secret = StaticSecret::from(raw);
+        let public = PublicKey::from(&secret);
+        Ok(Self { secret, public })
+    }
+
+    /// Builds a static identity from a fixed 32-byte seed, used by shared-secret provisioning.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(seed);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public
+    }
+}
+
+/// How a node obtains its static identity and decides who to trust.
+///
+/// *Shared-secret* mode keeps the original one-passphrase-for-everyone model:
+/// every node derives the same X25519 keypair from the passphrase, so "trust"
+/// reduces to verifying the peer revealed that exact same public key.
+/// *Explicit-trust* mode gives each node its own random keypair and reads a
+/// `trusted_keys` file of peer public keys instead.
+pub enum ProvisioningMode {
+    SharedSecret,
+    ExplicitTrust,
+}
+
+impl ProvisioningMode {
+    /// Reads `PROVISIONING_MODE` from the environment, defaulting to
+    /// shared-secret so existing single-password deployments keep working.
+    pub fn from_env() -> Self {
+        match std::env::var("PROVISIONING_MODE").as_deref() {
+            Ok("explicit-trust") => ProvisioningMode::ExplicitTrust,
+            _ => ProvisioningMode::SharedSecret,
+        }
+    }
+}
+
+/// Derives the static identity and trust set for `mode`.
+///
+/// In shared-secret mode `master_key` (the Argon2 output already derived from
+/// the node's passphrase) is stretched through HKDF into an X25519 seed, so
+/// every node that was given the same passphrase ends up with the identical
+/// keypair and therefore trusts itself. In explicit-trust mode the passphrase
+/// isn't involved at all: a random keypair is generated on first run and the
+/// `trusted_keys` file supplies the peer set.
+pub fn provision(
+    mode: &ProvisioningMode,
+    port: u16,
+    master_key: Option<&[u8; 32]>,
+) -> Result<(StaticIdentity, TrustedPeers)> {
+    match mode {
+        ProvisioningMode::SharedSecret => {
+            let master_key = master_key.ok_or("Shared-secret mode requires a master key")?;
+            let seed = hkdf_derive(master_key, b"vishwarupa static identity seed")?;
+            let identity = StaticIdentity::from_seed(seed);
+            let trusted = TrustedPeers::new(vec![*identity.public_key().as_bytes()]);
+            Ok((identity, trusted))
+        }
+        ProvisioningMode::ExplicitTrust => {
+            let identity = StaticIdentity::load_or_generate(port)?;
+            let trusted = TrustedPeers::load("trusted_keys")?;
+            Ok((identity, trusted))
+        }
+    }
+}
+
+/// Peers this node will complete a handshake with. Anyone whose revealed
+/// static key isn't in this set gets the connection aborted. The key list
+/// is mutex-guarded (like `membership::Membership`'s table) so a
+/// beacon-learned key can be folded in through `&self` from wherever a
+/// beacon is consumed, without needing a `NodeContext` field wrapped in its
+/// own lock.
+#[derive(Default)]
+pub struct TrustedPeers {
+    keys: Mutex<Vec<[u8; 32]>>,
+}
+
+impl TrustedPeers {
+    pub fn new(keys: Vec<[u8; 32]>) -> Self {
+        Self { keys: Mutex::new(keys) }
+    }
+
+    /// Reads a `trusted_keys` file: one hex-encoded X25519 public key per line.
+    pub fn load(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        let mut keys = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bytes = hex::decode(line)?;
+            if bytes.len() != 32 {
+                return Err(format!("Invalid trusted key entry: {}", line).into());
+            }
+            let mut raw = [0u8; 32];
+            raw.copy_from_slice(&bytes);
+            keys.push(raw);
+        }
+        Ok(Self::new(keys))
+    }
+
+    pub fn trusts(&self, peer: &PublicKey) -> bool {
+        self.keys.lock().unwrap().iter().any(|k| k == peer.as_bytes())
+    }
+
+    /// Folds a peer's static key into the trust set if it isn't already
+    /// there - used to wire a beacon-learned key in under explicit-trust
+    /// mode, where address-based discovery alone can't satisfy `trusts()`.
+    pub fn insert(&self, peer: PublicKey) {
+        let mut keys = self.keys.lock().unwrap();
+        if !keys.iter().any(|k| k == peer.as_bytes()) {
+            keys.push(*peer.as_bytes());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.lock().unwrap().is_empty()
+    }
+}
+
+/// Directional ChaCha20Poly1305 keys established by a completed handshake.
+struct SessionKeys {
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+}
+
+const REKEY_INFO: &[u8] = b"rekey";
+/// Ratchet the directional keys after this many frames...
+const REKEY_FRAME_INTERVAL: u64 = 10_000;
+/// ...or after this much wall-clock time, whichever comes first.
+const REKEY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+/// Width of the sliding replay window: a frame fewer than this many
+/// sequence numbers behind the highest seen so far can still be accepted
+/// out of order.
+const REPLAY_WINDOW: u64 = 64;
+const FLAG_REKEY: u8 = 0x01;
+
+// `protocol::MAX_BODY_LEN` (64MiB) bounds the inner protocol frame, plus
+// room for this layer's 9-byte seq/flags header and the AEAD tag. A peer
+// that's completed the handshake still isn't trusted not to lie about a
+// frame's length, so this has to be checked before `body` is allocated -
+// otherwise a single 4-byte length prefix claiming ~4GiB lets any
+// handshaken peer OOM us before we've read a single body byte.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024 + 9 + 16 + 4096;
+
+fn nonce_from_seq(seq: u64) -> Nonce {
+    let mut bytes = [0u8; 12];
+    bytes[4..].copy_from_slice(&seq.to_be_bytes());
+    *Nonce::from_slice(&bytes)
+}
+
+fn ratchet(key: &[u8; 32]) -> Result<[u8; 32]> {
+    hkdf_derive(key, REKEY_INFO)
+}
+
+/// Tracks the highest sequence number seen plus a bitmap of the
+/// `REPLAY_WINDOW` sequence numbers below it, so frames delayed or reordered
+/// in flight are still accepted while exact duplicates and stale replays are
+/// rejected.
+struct ReplayWindow {
+    highest: Option<u64>,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        Self { highest: None, seen: 0 }
+    }
+
+    /// Returns `true` if `seq` is new and should be accepted.
+    fn accept(&mut self, seq: u64) -> bool {
+        let highest = match self.highest {
+            None => {
+                self.highest = Some(seq);
+                self.seen = 1;
+                return true;
+            }
+            Some(h) => h,
+        };
+
+        if seq > highest {
+            let shift = seq - highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = Some(seq);
+            true
+        } else {
+            let age = highest - seq;
+            if age >= REPLAY_WINDOW {
+                return false;
+            }
+            let mask = 1u64 << age;
+            if self.seen & mask != 0 {
+                false
+            } else {
+                self.seen |= mask;
+                true
+            }
+        }
+    }
+}
+
+/// An authenticated, encrypted session wrapping a `TcpStream`. All framing
+/// after the handshake goes through `send_frame`/`recv_frame`. Every frame
+/// carries an explicit sequence number that seeds the AEAD nonce and drives
+/// replay detection, and the directional keys are ratcheted forward with
+/// HKDF once enough frames or enough time has passed under the current key.
+pub struct SecureStream {
+    stream: TcpStream,
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    send_cipher: ChaCha20Poly1305,
+    recv_cipher: ChaCha20Poly1305,
+    send_seq: u64,
+    frames_since_rekey: u64,
+    last_rekey: std::time::Instant,
+    replay_window: ReplayWindow,
+}
+
+impl SecureStream {
+    fn new(stream: TcpStream, keys: SessionKeys) -> Self {
+        Self {
+            stream,
+            send_cipher: ChaCha20Poly1305::new(Key::from_slice(&keys.send_key)),
+            recv_cipher: ChaCha20Poly1305::new(Key::from_slice(&keys.recv_key)),
+            send_key: keys.send_key,
+            recv_key: keys.recv_key,
+            send_seq: 0,
+            frames_since_rekey: 0,
+            last_rekey: std::time::Instant::now(),
+            replay_window: ReplayWindow::new(),
+        }
+    }
+
+    /// Initiates the handshake as the connecting side.
+    pub async fn initiate(
+        mut stream: TcpStream,
+        identity: &StaticIdentity,
+        trusted: &TrustedPeers,
+    ) -> Result<Self> {
+        let e_i = EphemeralSecret::random_from_rng(OsRng);
+        let e_i_pub = PublicKey::from(&e_i);
+
+        stream.write_all(e_i_pub.as_bytes()).await?;
+
+        let mut e_r_bytes = [0u8; 32];
+        stream.read_exact(&mut e_r_bytes).await?;
+        let e_r_pub = PublicKey::from(e_r_bytes);
+
+        let dh_ee = e_i.diffie_hellman(&e_r_pub);
+        let ephemeral_key = hkdf_derive(dh_ee.as_bytes(), EPHEMERAL_INFO)?;
+        let ephemeral_cipher = ChaCha20Poly1305::new(Key::from_slice(&ephemeral_key));
+
+        let mut sealed_len_buf = [0u8; 4];
+        stream.read_exact(&mut sealed_len_buf).await?;
+        let sealed_len = u32::from_be_bytes(sealed_len_buf) as usize;
+        let mut sealed = vec![0u8; sealed_len];
+        stream.read_exact(&mut sealed).await?;
+
+        let static_bytes = ephemeral_cipher
+            .decrypt(Nonce::from_slice(&RESPONDER_STATIC_NONCE), sealed.as_ref())
+            .map_err(|e| format!("Failed to open responder static key: {:?}", e))?;
+        if static_bytes.len() != 32 {
+            return Err("Malformed responder static key".into());
+        }
+        let mut s_r_raw = [0u8; 32];
+        s_r_raw.copy_from_slice(&static_bytes);
+        let s_r_pub = PublicKey::from(s_r_raw);
+
+        if !trusted.trusts(&s_r_pub) {
+            return Err("Responder static key is not trusted".into());
+        }
+
+        // Seal our own static key under the same ephemeral-ephemeral key
+        // (a distinct nonce from the responder's flight) instead of
+        // sending it in the clear, so a passive observer can't tell which
+        // node is dialing out just by watching the handshake.
+        let sealed_self = ephemeral_cipher
+            .encrypt(Nonce::from_slice(&INITIATOR_STATIC_NONCE), identity.public_key().as_bytes().as_slice())
+            .map_err(|e| format!("Failed to seal initiator static key: {:?}", e))?;
+        stream.write_all(&(sealed_self.len() as u32).to_be_bytes()).await?;
+        stream.write_all(&sealed_self).await?;
+
+        let dh_es = e_i.diffie_hellman(&s_r_pub);
+        let dh_se = identity.secret.diffie_hellman(&e_r_pub);
+
+        let keys = derive_session_keys(dh_ee.as_bytes(), dh_es.as_bytes(), dh_se.as_bytes(), true)?;
+        Ok(Self::new(stream, keys))
+    }
+
+    /// Accepts the handshake as the listening side.
+    pub async fn accept(
+        mut stream: TcpStream,
+        identity: &StaticIdentity,
+        trusted: &TrustedPeers,
+    ) -> Result<Self> {
+        let mut e_i_bytes = [0u8; 32];
+        stream.read_exact(&mut e_i_bytes).await?;
+        let e_i_pub = PublicKey::from(e_i_bytes);
+
+        let e_r = EphemeralSecret::random_from_rng(OsRng);
+        let e_r_pub = PublicKey::from(&e_r);
+
+        let dh_ee = e_r.diffie_hellman(&e_i_pub);
+        let ephemeral_key = hkdf_derive(dh_ee.as_bytes(), EPHEMERAL_INFO)?;
+        let ephemeral_cipher = ChaCha20Poly1305::new(Key::from_slice(&ephemeral_key));
+        let sealed = ephemeral_cipher
+            .encrypt(Nonce::from_slice(&RESPONDER_STATIC_NONCE), identity.public_key().as_bytes().as_slice())
+            .map_err(|e| format!("Failed to seal static key: {:?}", e))?;
+
+        stream.write_all(e_r_pub.as_bytes()).await?;
+        stream.write_all(&(sealed.len() as u32).to_be_bytes()).await?;
+        stream.write_all(&sealed).await?;
+
+        let mut sealed_i_len_buf = [0u8; 4];
+        stream.read_exact(&mut sealed_i_len_buf).await?;
+        let sealed_i_len = u32::from_be_bytes(sealed_i_len_buf) as usize;
+        let mut sealed_i = vec![0u8; sealed_i_len];
+        stream.read_exact(&mut sealed_i).await?;
+
+        let static_bytes = ephemeral_cipher
+            .decrypt(Nonce::from_slice(&INITIATOR_STATIC_NONCE), sealed_i.as_ref())
+            .map_err(|e| format!("Failed to open initiator static key: {:?}", e))?;
+        if static_bytes.len() != 32 {
+            return Err("Malformed initiator static key".into());
+        }
+        let mut s_i_raw = [0u8; 32];
+        s_i_raw.copy_from_slice(&static_bytes);
+        let s_i_pub = PublicKey::from(s_i_raw);
+
+        if !trusted.trusts(&s_i_pub) {
+            return Err("Initiator static key is not trusted".into());
+        }
+
+        let dh_es = identity.secret.diffie_hellman(&e_i_pub);
+        let dh_se = e_r.diffie_hellman(&s_i_pub);
+
+        let keys = derive_session_keys(dh_ee.as_bytes(), dh_es.as_bytes(), dh_se.as_bytes(), false)?;
+        Ok(Self::new(stream, keys))
+    }
+
+    async fn write_raw_frame(&mut self, seq: u64, flags: u8, plaintext: &[u8]) -> Result<()> {
+        let nonce = nonce_from_seq(seq);
+        let ciphertext = self
+            .send_cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|e| format!("Frame encryption failed: {:?}", e))?;
+
+        let mut body = Vec::with_capacity(9 + ciphertext.len());
+        body.extend_from_slice(&seq.to_be_bytes());
+        body.push(flags);
+        body.extend_from_slice(&ciphertext);
+
+        self.stream.write_all(&(body.len() as u32).to_be_bytes()).await?;
+        self.stream.write_all(&body).await?;
+        Ok(())
+    }
+
+    /// Sends a data frame, transparently emitting a rekey marker first and
+    /// ratcheting `send_cipher` if this key has carried enough frames or been
+    /// in use long enough.
+    pub async fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        if self.frames_since_rekey >= REKEY_FRAME_INTERVAL || self.last_rekey.elapsed() >= REKEY_INTERVAL {
+            let seq = self.send_seq;
+            self.send_seq += 1;
+            self.write_raw_frame(seq, FLAG_REKEY, &[]).await?;
+
+            self.send_key = ratchet(&self.send_key)?;
+            self.send_cipher = ChaCha20Poly1305::new(Key::from_slice(&self.send_key));
+            self.frames_since_rekey = 0;
+            self.last_rekey = std::time::Instant::now();
+        }
+
+        let seq = self.send_seq;
+        self.send_seq += 1;
+        self.frames_since_rekey += 1;
+        self.write_raw_frame(seq, 0, payload).await
+    }
+
+    /// Receives the next data frame, applying replay protection and
+    /// transparently ratcheting `recv_cipher` when a rekey marker arrives.
+    pub async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let mut len_buf = [0u8; 4];
+            self.stream.read_exact(&mut len_buf).await?;
+            let len = u32::from_be_bytes(len_buf) as usize;
+            if len < 9 {
+                return Err("Frame too short".into());
+            }
+            if len > MAX_FRAME_LEN {
+                return Err(format!("Frame too large: {} bytes", len).into());
+            }
+            let mut body = vec![0u8; len];
+            self.stream.read_exact(&mut body).await?;
+
+            let seq = u64::from_be_bytes(body[0..8].try_into()?);
+            let flags = body[8];
+            let ciphertext = &body[9..];
+
+            if !self.replay_window.accept(seq) {
+                return Err("Rejected replayed or stale frame".into());
+            }
+
+            let nonce = nonce_from_seq(seq);
+            let plaintext = self
+                .recv_cipher
+                .decrypt(&nonce, ciphertext)
+                .map_err(|e| format!("Frame decryption failed: {:?}", e))?;
+
+            if flags & FLAG_REKEY != 0 {
+                self.recv_key = ratchet(&self.recv_key)?;
+                self.recv_cipher = ChaCha20Poly1305::new(Key::from_slice(&self.recv_key));
+                continue;
+            }
+
+            return Ok(plaintext);
+        }
+    }
+}
+
+fn hkdf_derive(ikm: &[u8], info: &[u8]) -> Result<[u8; 32]> {
+    let hk = Hkdf::<Sha256>::new(None, ikm);
+    let mut out = [0u8; 32];
+    hk.expand(info, &mut out)
+        .map_err(|_| "HKDF expand failed")?;
+    Ok(out)
+}
+
+/// Mixes the three DH outputs through HKDF-SHA256 and splits the result into
+/// two directional keys. `initiator` picks which half is "send" vs "recv" so
+/// both ends agree on the same pair of keys.
+fn derive_session_keys(
+    dh_ee: &[u8],
+    dh_es: &[u8],
+    dh_se: &[u8],
+    initiator: bool,
+) -> Result<SessionKeys> {
+    let mut ikm = Vec::with_capacity(dh_ee.len() + dh_es.len() + dh_se.len());
+    ikm.extend_from_slice(dh_ee);
+    ikm.extend_from_slice(dh_es);
+    ikm.extend_from_slice(dh_se);
+
+    let hk = Hkdf::<Sha256>::new(None, &ikm);
+    let mut okm = [0u8; 64];
+    hk.expand(HANDSHAKE_INFO, &mut okm)
+        .map_err(|_| "HKDF expand failed")?;
+
+    let (a, b) = okm.split_at(32);
+    let mut key_a = [0u8; 32];
+    let mut key_b = [0u8; 32];
+    key_a.copy_from_slice(a);
+    key_b.copy_from_slice(b);
+
+    if initiator {
+        Ok(SessionKeys { send_key: key_a, recv_key: key_b })
+    } else {
+        Ok(SessionKeys { send_key: key_b, recv_key: key_a })
+    }
+}
@@ -1,3 +1,12 @@
+mod handshake;
+mod beacon;
+mod protocol;
+mod dht;
+mod membership;
+mod signing;
+mod watch;
+mod mapreduce;
+
 use std::sync::Arc;
 use std::fs;
 use std::path::Path;
@@ -11,6 +20,8 @@ use argon2::{Argon2, PasswordHasher, password_hash::SaltString};
 use zeroize::Zeroizing;
 use rand::Rng;
 use mdns_sd::{ServiceDaemon, ServiceInfo};
+use sha2::{Digest, Sha256};
+use handshake::{SecureStream, StaticIdentity, TrustedPeers};
 
 const CHUNK: usize = 4 * 1024 * 1024;
 
@@ -23,6 +34,32 @@ fn get_listen_port() -> u16 {
 const DATA_SHARDS: usize = 6;
 const PARITY_SHARDS: usize = 4;
 
+/// How many of the most recent versions of a logical file name to keep;
+/// older versions (and their manifests) are evicted on the next upload.
+fn max_versions() -> usize {
+    std::env::var("MAX_VERSIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5)
+}
+
+/// How many distinct alive peers each chunk's shards are replicated onto,
+/// chosen by DHT closeness to the shard's content id.
+fn replication_factor() -> usize {
+    std::env::var("REPLICATION_FACTOR")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2)
+        .max(1)
+}
+
+fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -39,6 +76,7 @@ struct ShardMetadata {
     chunk_index: usize,
     shard_index: usize,
     nonce: Vec<u8>,
+    tag: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -50,6 +88,40 @@ struct Manifest {
     encryption_key: Vec<u8>,
     shard_map: Vec<ShardLocation>,
     chunks: Vec<ChunkInfo>,
+    version_timestamp: u64,
+    /// SHA-256 of the whole original file, checked after reassembly.
+    file_digest: Vec<u8>,
+    /// `(device_id, Ed25519 signature)` pairs over `manifest_signing_payload`:
+    /// the uploader's own signature first, followed by any cross-signatures
+    /// from devices that have independently verified this manifest.
+    signatures: Vec<(String, Vec<u8>)>,
+    /// A deleted version - chunkless, shardless - recorded by `watch` when
+    /// it sees a file removed, so the version chain can tell "removed" apart
+    /// from "a version whose shards are just unreachable".
+    deleted: bool,
+}
+
+/// The bytes a manifest's signatures are computed over: everything that
+/// pins down *content* (file id, whole-file digest, per-chunk digests) but
+/// none of the placement/transport metadata that can legitimately change
+/// without the underlying file changing.
+fn manifest_signing_payload(file_id: &str, file_digest: &[u8], chunks: &[ChunkInfo]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(file_id.as_bytes());
+    payload.extend_from_slice(file_digest);
+    for chunk in chunks {
+        payload.extend_from_slice(&chunk.digest);
+    }
+    payload
+}
+
+/// One entry in a logical file name's version history: which manifest
+/// (`file_id`) it points to, and when it was uploaded.
+#[derive(Serialize, Deserialize, Clone)]
+struct VersionEntry {
+    file_id: String,
+    timestamp: u64,
+    file_size: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -60,6 +132,7 @@ struct ShardLocation {
     device_address: String,
     shard_id: String,
     nonce: Vec<u8>,
+    tag: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -67,6 +140,9 @@ struct ChunkInfo {
     chunk_index: usize,
     encrypted_size: usize,
     nonce: Vec<u8>,
+    /// SHA-256 of this chunk's plaintext, checked against the reassembled
+    /// chunk on `download` before it's accepted.
+    digest: Vec<u8>,
 }
 
 struct Database {
@@ -74,6 +150,54 @@ struct Database {
     storage_dir: String,
 }
 
+/// Bundles everything a connection needs: shard storage, the identity and
+/// trust set used to authenticate the handshake before any GET/PUT happens,
+/// the DHT routing table, SWIM membership, and the manifest signing key.
+struct NodeContext {
+    db: Database,
+    identity: StaticIdentity,
+    trusted: TrustedPeers,
+    routing_table: dht::RoutingTable,
+    membership: membership::Membership,
+    signing_key: ed25519_dalek::SigningKey,
+}
+
+impl NodeContext {
+    fn new(device_id: &str) -> Result<Self> {
+        let port = get_listen_port();
+        let db = Database::new(device_id)?;
+
+        let mode = handshake::ProvisioningMode::from_env();
+        let master_key = match mode {
+            handshake::ProvisioningMode::SharedSecret => Some(get_master_key()?),
+            handshake::ProvisioningMode::ExplicitTrust => None,
+        };
+        let (identity, trusted) = handshake::provision(&mode, port, master_key.as_deref())?;
+        let routing_table = dht::RoutingTable::new(dht::node_id_for_device(device_id));
+        let self_device = Device {
+            device_id: device_id.to_string(),
+            device_type: "agent".to_string(),
+            address: get_local_ip(),
+            port,
+        };
+        let membership = membership::Membership::new(self_device);
+
+        let signing_key = signing::load_or_generate(device_id)?;
+        db.store_signer_key(device_id, signing_key.verifying_key().as_bytes())?;
+
+        Ok(Self { db, identity, trusted, routing_table, membership, signing_key })
+    }
+
+    /// Feeds freshly discovered peers into both the Kademlia routing table
+    /// and the SWIM membership list.
+    fn seed_peers(&self, devices: &[Device]) {
+        for device in devices {
+            self.routing_table.insert(dht::node_id_for_device(&device.device_id), device.clone());
+        }
+        self.membership.seed(devices);
+    }
+}
+
 impl Database {
     fn new(device_id: &str) -> Result<Self> {
         let short_id = if device_id.len() >= 8 {
@@ -109,17 +233,113 @@ impl Database {
         decrypt_manifest(&data)
     }
 
-    fn list_files(&self) -> Result<Vec<String>> {
-        let mut files = Vec::new();
-        for item in self.data.scan_prefix(b"manifest:") {
-            let (key, _) = item?;
+    /// Records which devices were told (via the DHT) to advertise content
+    /// addressed by `key`, so a later `FIND_VALUE` from a peer can be
+    /// answered without needing the original manifest.
+    fn store_providers(&self, key: &dht::NodeId, providers: &[Device]) -> Result<()> {
+        let db_key = format!("providers:{}", hex::encode(key));
+        self.data.insert(db_key.as_bytes(), serde_json::to_vec(providers)?)?;
+        Ok(())
+    }
+
+    fn get_providers(&self, key: &dht::NodeId) -> Result<Option<Vec<Device>>> {
+        let db_key = format!("providers:{}", hex::encode(key));
+        match self.data.get(db_key.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the Ed25519 public key a device signs manifests with, so a
+    /// later `verify` can check its signatures. Trust-on-first-use: the
+    /// first key seen for a device id wins.
+    fn store_signer_key(&self, device_id: &str, public_key: &[u8]) -> Result<()> {
+        let key = format!("signer_key:{}", device_id);
+        if self.data.get(key.as_bytes())?.is_none() {
+            self.data.insert(key.as_bytes(), public_key)?;
+        }
+        Ok(())
+    }
+
+    fn get_signer_key(&self, device_id: &str) -> Result<Option<Vec<u8>>> {
+        let key = format!("signer_key:{}", device_id);
+        Ok(self.data.get(key.as_bytes())?.map(|bytes| bytes.to_vec()))
+    }
+
+    /// Appends a new version to `name`'s history and evicts the oldest
+    /// versions past `retention`, dropping their manifests too so an
+    /// unbounded version chain doesn't keep every upload forever. Returns
+    /// the evicted manifests (best-effort - a manifest already missing
+    /// locally is just skipped) so the caller can garbage-collect their
+    /// shards from the peers storing them; this method only touches the
+    /// local version/manifest index, not the network.
+    fn record_version(&self, name: &str, entry: VersionEntry, retention: usize) -> Result<Vec<Manifest>> {
+        let key = format!("versions:{}", name);
+        let mut versions: Vec<VersionEntry> = match self.data.get(key.as_bytes())? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => Vec::new(),
+        };
+        versions.push(entry);
+        versions.sort_by_key(|v| v.timestamp);
+        let mut evicted_manifests = Vec::new();
+        while versions.len() > retention {
+            let evicted = versions.remove(0);
+            if let Ok(manifest) = self.get_manifest(&evicted.file_id) {
+                evicted_manifests.push(manifest);
+            }
+            self.data.remove(format!("manifest:{}", evicted.file_id).as_bytes())?;
+        }
+        self.data.insert(key.as_bytes(), serde_json::to_vec(&versions)?)?;
+        Ok(evicted_manifests)
+    }
+
+    /// `name`'s version history, most recent first.
+    fn list_versions(&self, name: &str) -> Result<Vec<VersionEntry>> {
+        let key = format!("versions:{}", name);
+        match self.data.get(key.as_bytes())? {
+            Some(bytes) => {
+                let mut versions: Vec<VersionEntry> = serde_json::from_slice(&bytes)?;
+                versions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+                Ok(versions)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Every logical file name known locally, alongside how many versions
+    /// it has - backs `list`'s per-file version count.
+    fn list_named_files(&self) -> Result<Vec<(String, usize)>> {
+        let mut named = Vec::new();
+        for item in self.data.scan_prefix(b"versions:") {
+            let (key, value) = item?;
             if let Ok(key_str) = std::str::from_utf8(&key) {
-                if let Some(file_id) = key_str.strip_prefix("manifest:") {
-                    files.push(file_id.to_string());
+                if let Some(name) = key_str.strip_prefix("versions:") {
+                    let versions: Vec<VersionEntry> = serde_json::from_slice(&value)?;
+                    named.push((name.to_string(), versions.len()));
                 }
             }
         }
-        Ok(files)
+        Ok(named)
+    }
+
+    /// Persists a finished map-reduce job's stats so `jobs` can report on
+    /// it later - jobs run to completion within one CLI invocation, so
+    /// there's no live daemon state to query, only this history.
+    fn record_job(&self, job: &mapreduce::JobRecord) -> Result<()> {
+        let key = format!("job:{}", job.job_id);
+        self.data.insert(key.as_bytes(), serde_json::to_vec(job)?)?;
+        Ok(())
+    }
+
+    /// Every recorded job, most recent first.
+    fn list_jobs(&self) -> Result<Vec<mapreduce::JobRecord>> {
+        let mut jobs = Vec::new();
+        for item in self.data.scan_prefix(b"job:") {
+            let (_, value) = item?;
+            jobs.push(serde_json::from_slice(&value)?);
+        }
+        jobs.sort_by(|a: &mapreduce::JobRecord, b: &mapreduce::JobRecord| b.started_at.cmp(&a.started_at));
+        Ok(jobs)
     }
 }
 
@@ -158,7 +378,7 @@ fn decrypt_manifest(data: &[u8]) -> Result<Manifest> {
     Ok(serde_json::from_slice(&json)?)
 }
 
-fn get_master_key() -> Result<Zeroizing<[u8; 32]>> {
+pub(crate) fn get_master_key() -> Result<Zeroizing<[u8; 32]>> {
     // Each port gets its own key file so agents don't conflict
     let port = get_listen_port();
     let key_file = format!("master_{}.key", port);
@@ -289,7 +509,7 @@ async fn start_mdns_advertise(device_id: String) -> Result<()> {
     Ok(())
 }
 
-async fn discover_devices() -> Result<Vec<Device>> {
+async fn discover_devices(trusted: &TrustedPeers) -> Result<Vec<Device>> {
     let mdns = ServiceDaemon::new()?;
     let service_type = "_vishwarupa._tcp.local.";
     let receiver = mdns.browse(service_type)?;
@@ -320,22 +540,28 @@ async fn discover_devices() -> Result<Vec<Device>> {
             }
         }
     }
-    
+
+    let mut beacon_peers = beacon::discover_beacon_peers(trusted);
+    if !beacon_peers.is_empty() {
+        println!("Discovered {} devices via beacon", beacon_peers.len());
+        devices.append(&mut beacon_peers);
+    }
+
     println!("Discovered {} devices", devices.len());
     Ok(devices)
 }
 
-async fn listen(db: Arc<Database>) -> Result<()> {
+async fn listen(ctx: Arc<NodeContext>) -> Result<()> {
     let port = get_listen_port();
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     println!("Listening on port {}", port);
-    
+
     loop {
         match listener.accept().await {
             Ok((stream, addr)) => {
-                let db = Arc::clone(&db);
+                let ctx = Arc::clone(&ctx);
                 tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, db).await {
+                    if let Err(e) = handle_connection(stream, ctx).await {
                         eprintln!("Connection error from {}: {}", addr, e);
                     }
                 });
@@ -345,64 +571,180 @@ async fn listen(db: Arc<Database>) -> Result<()> {
     }
 }
 
-async fn handle_connection(mut stream: TcpStream, db: Arc<Database>) -> Result<()> {
-    let mut header = [0u8; 3];
-    stream.read_exact(&mut header).await?;
-    
-    if &header == b"GET" {
-        stream.read_exact(&mut [0u8; 1]).await?; // Read ':'
-        let mut shard_id = String::new();
-        let mut buf = [0u8; 1];
-        while stream.read_exact(&mut buf).await.is_ok() {
-            if buf[0] == b'\n' || buf[0] == 0 {
-                break;
-            }
-            shard_id.push(buf[0] as char);
+async fn handle_connection(stream: TcpStream, ctx: Arc<NodeContext>) -> Result<()> {
+    let mut secure = SecureStream::accept(stream, &ctx.identity, &ctx.trusted).await?;
+    let frame = secure.recv_frame().await?;
+
+    match protocol::decode_request(&frame) {
+        Ok(protocol::Request::Store { metadata, shard }) => {
+            let shard_id = Uuid::new_v4().to_string();
+            let shard_path = format!("{}/{}", ctx.db.storage_path(), shard_id);
+            let meta_path = format!("{}/{}.meta", ctx.db.storage_path(), shard_id);
+
+            fs::write(&meta_path, serde_json::to_vec(&metadata)?)?;
+            fs::write(&shard_path, &shard)?;
+
+            let ack = protocol::Ack { ok: true, message: shard_id };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::Store, &ack, &[])?).await?;
         }
-        
-        let shard_path = format!("{}/{}", db.storage_path(), shard_id.trim());
-        match fs::read(&shard_path) {
-            Ok(data) => {
-                stream.write_all(&data).await?;
-            }
-            Err(_) => {
-                stream.write_all(b"ERR").await?;
+        Ok(protocol::Request::Fetch { shard_id }) => {
+            let shard_path = format!("{}/{}", ctx.db.storage_path(), shard_id);
+            match fs::read(&shard_path) {
+                Ok(data) => {
+                    let ack = protocol::Ack { ok: true, message: String::new() };
+                    secure.send_frame(&protocol::encode_response(protocol::Opcode::Fetch, &ack, &data)?).await?;
+                }
+                Err(e) => {
+                    let ack = protocol::Ack { ok: false, message: e.to_string() };
+                    secure.send_frame(&protocol::encode_response(protocol::Opcode::Fetch, &ack, &[])?).await?;
+                }
             }
         }
-    } else {
-        let mut len_buf = [header[0], header[1], header[2], 0];
-        stream.read_exact(&mut len_buf[3..4]).await?;
-        let meta_len = u32::from_be_bytes(len_buf) as usize;
-        
-        let mut meta_bytes = vec![0u8; meta_len];
-        stream.read_exact(&mut meta_bytes).await?;
-        
-        let mut shard_data = Vec::new();
-        stream.read_to_end(&mut shard_data).await?;
-        
-        match serde_json::from_slice::<ShardMetadata>(&meta_bytes) {
-            Ok(_) => {
-                let shard_id = Uuid::new_v4().to_string();
-                let shard_path = format!("{}/{}", db.storage_path(), shard_id);
-                let meta_path = format!("{}/{}.meta", db.storage_path(), shard_id);
-                
-                fs::write(&meta_path, &meta_bytes)?;
-                fs::write(&shard_path, &shard_data)?;
-                
-                stream.write_all(shard_id.as_bytes()).await?;
-            }
-            Err(_) => {
-                stream.write_all(b"ERR").await?;
+        Ok(protocol::Request::Delete { shard_id }) => {
+            let shard_path = format!("{}/{}", ctx.db.storage_path(), shard_id);
+            let meta_path = format!("{}/{}.meta", ctx.db.storage_path(), shard_id);
+            let _ = fs::remove_file(&shard_path);
+            let _ = fs::remove_file(&meta_path);
+            let ack = protocol::Ack { ok: true, message: String::new() };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::Delete, &ack, &[])?).await?;
+        }
+        Ok(protocol::Request::Stat { shard_id }) => {
+            let shard_path = format!("{}/{}", ctx.db.storage_path(), shard_id);
+            let response = match fs::metadata(&shard_path) {
+                Ok(meta) => protocol::StatResponse { exists: true, size: meta.len() },
+                Err(_) => protocol::StatResponse { exists: false, size: 0 },
+            };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::Stat, &response, &[])?).await?;
+        }
+        Ok(protocol::Request::List { file_id }) => {
+            let shard_ids = list_shard_ids_for_file(&ctx, &file_id)?;
+            let response = protocol::ListResponse { shard_ids };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::List, &response, &[])?).await?;
+        }
+        Ok(protocol::Request::FindNode { target }) => {
+            let peers = ctx.routing_table.closest(&target, dht::K)
+                .into_iter()
+                .map(|p| protocol::PeerInfo { id: p.id, device: p.device })
+                .collect();
+            let response = protocol::FindNodeResponse { peers };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::FindNode, &response, &[])?).await?;
+        }
+        Ok(protocol::Request::FindValue { key }) => {
+            let response = match ctx.db.get_providers(&key)? {
+                Some(providers) => protocol::FindValueResponse::Value(providers),
+                None => {
+                    let peers = ctx.routing_table.closest(&key, dht::K)
+                        .into_iter()
+                        .map(|p| protocol::PeerInfo { id: p.id, device: p.device })
+                        .collect();
+                    protocol::FindValueResponse::Peers(peers)
+                }
+            };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::FindValue, &response, &[])?).await?;
+        }
+        Ok(protocol::Request::StoreProviders { key, providers }) => {
+            ctx.db.store_providers(&key, &providers)?;
+            let ack = protocol::Ack { ok: true, message: String::new() };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::StoreProviders, &ack, &[])?).await?;
+        }
+        Ok(protocol::Request::Ping { from, updates }) => {
+            ctx.membership.ingest(&updates);
+            ctx.membership.seed(&[from]);
+            let response = protocol::PingAck { updates: ctx.membership.outgoing_updates() };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::Ping, &response, &[])?).await?;
+        }
+        Ok(protocol::Request::PingReq { target, updates }) => {
+            ctx.membership.ingest(&updates);
+            let ok = tokio::time::timeout(
+                membership::PING_TIMEOUT,
+                membership::ping(&ctx, &target, ctx.membership.outgoing_updates()),
+            )
+            .await
+            .map(|r| r.is_ok())
+            .unwrap_or(false);
+            let ack = protocol::Ack { ok, message: String::new() };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::PingReq, &ack, &[])?).await?;
+        }
+        Ok(protocol::Request::MapTask { file_id, chunk_index, task, encryption_key, chunk_info, chunk_shards }) => {
+            println!("MapTask: running '{}' on {} chunk {}", task, file_id, chunk_index);
+            let result = reconstruct_chunk(&ctx, &encryption_key, &chunk_info, &chunk_shards)
+                .await
+                .and_then(|chunk| mapreduce::apply_map(&task, &chunk));
+            match result {
+                Ok(pairs) => {
+                    let ack = protocol::Ack { ok: true, message: String::new() };
+                    let raw = serde_json::to_vec(&pairs)?;
+                    secure.send_frame(&protocol::encode_response(protocol::Opcode::MapTask, &ack, &raw)?).await?;
+                }
+                Err(e) => {
+                    let ack = protocol::Ack { ok: false, message: e.to_string() };
+                    secure.send_frame(&protocol::encode_response(protocol::Opcode::MapTask, &ack, &[])?).await?;
+                }
             }
         }
+        Ok(protocol::Request::Reduce { pairs }) => {
+            let reduced = mapreduce::reduce_sum(pairs);
+            let ack = protocol::Ack { ok: true, message: String::new() };
+            let raw = serde_json::to_vec(&reduced)?;
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::Reduce, &ack, &raw)?).await?;
+        }
+        Ok(protocol::Request::MembershipQuery) => {
+            let response = protocol::MembershipSnapshot { members: ctx.membership.snapshot() };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::MembershipQuery, &response, &[])?).await?;
+        }
+        Err(e) => {
+            let ack = protocol::Ack { ok: false, message: e.to_string() };
+            secure.send_frame(&protocol::encode_response(protocol::Opcode::Store, &ack, &[])?).await?;
+        }
     }
-    
+
     Ok(())
 }
 
-async fn upload(path: &str, db: Arc<Database>) -> Result<String> {
+/// Scans this node's `.meta` sidecar files for shards belonging to `file_id`,
+/// backing the `LIST` opcode.
+fn list_shard_ids_for_file(ctx: &NodeContext, file_id: &str) -> Result<Vec<String>> {
+    let mut shard_ids = Vec::new();
+    for entry in fs::read_dir(ctx.db.storage_path())? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("meta") {
+            continue;
+        }
+        let meta_bytes = fs::read(&path)?;
+        if let Ok(metadata) = serde_json::from_slice::<ShardMetadata>(&meta_bytes) {
+            if metadata.file_id == file_id {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    shard_ids.push(stem.to_string());
+                }
+            }
+        }
+    }
+    Ok(shard_ids)
+}
+
+/// Scans this node's `.meta` sidecar files for every shard it holds,
+/// regardless of file - backs the `store` command's local inventory view.
+fn local_shard_inventory(ctx: &NodeContext) -> Result<Vec<ShardMetadata>> {
+    let mut shards = Vec::new();
+    for entry in fs::read_dir(ctx.db.storage_path())? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("meta") {
+            continue;
+        }
+        let meta_bytes = fs::read(&path)?;
+        if let Ok(metadata) = serde_json::from_slice::<ShardMetadata>(&meta_bytes) {
+            shards.push(metadata);
+        }
+    }
+    Ok(shards)
+}
+
+async fn upload(path: &str, ctx: Arc<NodeContext>) -> Result<String> {
     let file = fs::read(path)?;
     let file_size = file.len();
+    let file_digest = Sha256::digest(&file).to_vec();
     let key: [u8; 32] = rand::thread_rng().gen();
     let file_id = Uuid::new_v4().to_string();
     let original_name = Path::new(path)
@@ -411,12 +753,25 @@ async fn upload(path: &str, db: Arc<Database>) -> Result<String> {
         .unwrap_or("unknown")
         .to_string();
     
-    let devices = discover_devices().await?;
+    let devices = discover_devices(&ctx.trusted).await?;
     if devices.is_empty() {
         return Err("No devices found on network".into());
     }
-    
-    println!("Found {} devices, uploading...", devices.len());
+
+    ctx.seed_peers(&devices);
+    // A CLI invocation's own `Membership` is brand new and hasn't had a
+    // single SWIM protocol period to find anything out for itself; pull in
+    // whatever a running daemon on the network has already determined
+    // instead of treating every freshly-seeded peer as unconditionally alive.
+    membership::sync_from_peers(&ctx, &devices).await;
+    let live_ids: std::collections::HashSet<_> =
+        ctx.membership.live_members().into_iter().map(|d| d.device_id).collect();
+    let devices: Vec<Device> = devices.into_iter().filter(|d| live_ids.contains(&d.device_id)).collect();
+    if devices.is_empty() {
+        return Err("No live devices available for replica placement".into());
+    }
+
+    println!("Found {} live devices, uploading...", devices.len());
 
     let mut shard_map = Vec::new();
     let mut chunks_info = Vec::new();
@@ -425,14 +780,16 @@ async fn upload(path: &str, db: Arc<Database>) -> Result<String> {
     for (chunk_idx, chunk) in file.chunks(CHUNK).enumerate() {
         println!("Chunk {}/{}", chunk_idx + 1, chunk_count);
         
+        let chunk_digest = Sha256::digest(chunk).to_vec();
         let compressed = lz4::block::compress(chunk, None, false)?;
         let (encrypted, nonce) = encrypt_chunk(&compressed, &key)?;
         let encrypted_size = encrypted.len();
-        
+
         chunks_info.push(ChunkInfo {
             chunk_index: chunk_idx,
             encrypted_size,
             nonce: nonce.clone(),
+            digest: chunk_digest,
         });
         
         let shard_size = (encrypted.len() + DATA_SHARDS - 1) / DATA_SHARDS;
@@ -457,31 +814,41 @@ async fn upload(path: &str, db: Arc<Database>) -> Result<String> {
         let rs = ReedSolomon::new(DATA_SHARDS, PARITY_SHARDS)?;
         rs.encode(&mut shards)?;
         
+        let replicas = replication_factor();
         for (shard_idx, shard) in shards.iter().enumerate() {
-            let target = &devices[shard_idx % devices.len()];
-            
+            let tag = shard_tag(&key, chunk_idx, shard_idx, shard);
+            let placement_key = dht::content_id(&format!("{}:{}:{}", file_id, chunk_idx, shard_idx));
+            let ranked_targets = dht::rank_by_closeness(&devices, placement_key);
+
             let metadata = ShardMetadata {
                 file_id: file_id.clone(),
                 chunk_index: chunk_idx,
                 shard_index: shard_idx,
                 nonce: nonce.clone(),
+                tag: tag.clone(),
             };
-            
-            print!("Sending shard {} to {}... ", shard_idx, target.device_id);
-            match send_shard(target, shard, &metadata).await {
-                Ok(shard_id) => {
-                    println!("✓");
-                    shard_map.push(ShardLocation {
-                        chunk_index: chunk_idx,
-                        shard_index: shard_idx,
-                        device_id: target.device_id.clone(),
-                        device_address: format!("{}:{}", target.address, target.port),
-                        shard_id,
-                        nonce: nonce.clone(),
-                    });
-                }
-                Err(e) => {
-                    println!("✗ ({})", e);
+
+            // Replicate onto the `replicas` closest alive peers, in
+            // closeness order, so `download` can fall through to the next
+            // one if the nearest holder turns out to be unreachable.
+            for target in ranked_targets.iter().take(replicas) {
+                print!("Sending shard {} (chunk {}) to {}... ", shard_idx, chunk_idx, target.device_id);
+                match send_shard(&ctx, target, shard, &metadata).await {
+                    Ok(shard_id) => {
+                        println!("✓");
+                        shard_map.push(ShardLocation {
+                            chunk_index: chunk_idx,
+                            shard_index: shard_idx,
+                            device_id: target.device_id.clone(),
+                            device_address: format!("{}:{}", target.address, target.port),
+                            shard_id,
+                            nonce: nonce.clone(),
+                            tag: tag.clone(),
+                        });
+                    }
+                    Err(e) => {
+                        println!("✗ ({})", e);
+                    }
                 }
             }
         }
@@ -493,18 +860,45 @@ async fn upload(path: &str, db: Arc<Database>) -> Result<String> {
         return Err(format!("Not enough shards stored: {}/{}", shard_count, DATA_SHARDS).into());
     }
     
+    let version_timestamp = now_unix();
+    let uploader_id = ctx.membership.device_id().to_string();
+    let signing_payload = manifest_signing_payload(&file_id, &file_digest, &chunks_info);
+    let uploader_signature = signing::sign(&ctx.signing_key, &signing_payload);
+
     let manifest = Manifest {
         file_id: file_id.clone(),
-        original_name,
+        original_name: original_name.clone(),
         file_size,
         chunk_count,
         encryption_key: key.to_vec(),
         shard_map,
         chunks: chunks_info,
+        version_timestamp,
+        file_digest,
+        signatures: vec![(uploader_id, uploader_signature)],
+        deleted: false,
     };
 
-    db.store_manifest(&manifest)?;
-    
+    ctx.db.store_manifest(&manifest)?;
+    let evicted = ctx.db.record_version(
+        &original_name,
+        VersionEntry { file_id: file_id.clone(), timestamp: version_timestamp, file_size },
+        max_versions(),
+    )?;
+    gc_evicted_shards(&ctx, evicted).await;
+
+    // Publish which devices hold this file's shards to the DHT so a peer
+    // without the manifest locally can still locate it by content hash.
+    let mut providers: Vec<Device> = Vec::new();
+    for loc in &manifest.shard_map {
+        if !providers.iter().any(|d| d.device_id == loc.device_id) {
+            if let Some(device) = devices.iter().find(|d| d.device_id == loc.device_id) {
+                providers.push(device.clone());
+            }
+        }
+    }
+    dht::store_providers(&ctx, &ctx.routing_table, dht::content_id(&file_id), providers).await;
+
     // Send manifest to server so all devices can see it
     let manifest_json = serde_json::to_string(&manifest)?;
     let client = reqwest::blocking::Client::new();
@@ -528,146 +922,265 @@ async fn upload(path: &str, db: Arc<Database>) -> Result<String> {
     println!("\n✓ Upload complete!");
     println!("  File ID: {}", file_id);
     println!("  Shards stored: {}", shard_count);
-    
+
     Ok(file_id)
 }
 
-async fn send_shard(device: &Device, shard: &[u8], metadata: &ShardMetadata) -> Result<String> {
-    let meta_json = serde_json::to_vec(metadata)?;
-    let meta_len = meta_json.len() as u32;
-    
-    let mut payload = Vec::new();
-    payload.extend_from_slice(&meta_len.to_be_bytes());
-    payload.extend_from_slice(&meta_json);
-    payload.extend_from_slice(shard);
-    
+/// Records that `original_name`'s latest version has been deleted, without
+/// re-uploading any chunks - an empty manifest version flagged `deleted`,
+/// chained onto the same version history `get-versions`/`list` already use.
+/// Used by `watch` so a remove under the watched directory is reflected the
+/// same way a new write is: as a new manifest version.
+async fn upload_tombstone(original_name: &str, ctx: Arc<NodeContext>) -> Result<String> {
+    let file_id = Uuid::new_v4().to_string();
+    let version_timestamp = now_unix();
+    let file_digest = Sha256::digest(b"").to_vec();
+    let uploader_id = ctx.membership.device_id().to_string();
+    let signing_payload = manifest_signing_payload(&file_id, &file_digest, &[]);
+    let uploader_signature = signing::sign(&ctx.signing_key, &signing_payload);
+
+    let manifest = Manifest {
+        file_id: file_id.clone(),
+        original_name: original_name.to_string(),
+        file_size: 0,
+        chunk_count: 0,
+        encryption_key: vec![0u8; 32],
+        shard_map: Vec::new(),
+        chunks: Vec::new(),
+        version_timestamp,
+        file_digest,
+        signatures: vec![(uploader_id, uploader_signature)],
+        deleted: true,
+    };
+
+    ctx.db.store_manifest(&manifest)?;
+    let evicted = ctx.db.record_version(
+        original_name,
+        VersionEntry { file_id: file_id.clone(), timestamp: version_timestamp, file_size: 0 },
+        max_versions(),
+    )?;
+    gc_evicted_shards(&ctx, evicted).await;
+
+    Ok(file_id)
+}
+
+async fn send_shard(ctx: &NodeContext, device: &Device, shard: &[u8], metadata: &ShardMetadata) -> Result<String> {
+    let payload = protocol::encode_store(metadata, shard)?;
     let addr = format!("{}:{}", device.address, device.port);
-    
-    // Add timeout
-    let connect_timeout = tokio::time::timeout(
+
+    let stream = tokio::time::timeout(
         tokio::time::Duration::from_secs(3),
         TcpStream::connect(&addr)
     ).await.map_err(|_| "Connection timeout")??;
-    
-    let mut stream = connect_timeout;
-    
-    // Write with timeout
+
+    let mut secure = tokio::time::timeout(
+        tokio::time::Duration::from_secs(5),
+        SecureStream::initiate(stream, &ctx.identity, &ctx.trusted)
+    ).await.map_err(|_| "Handshake timeout")??;
+
     tokio::time::timeout(
         tokio::time::Duration::from_secs(5),
-        stream.write_all(&payload)
+        secure.send_frame(&payload)
     ).await.map_err(|_| "Write timeout")??;
-    
-    // Shutdown write side to signal we're done
-    stream.shutdown().await?;
-    
-    // Read response - fixed size UUID (36 bytes) or "ERR" (3 bytes)
-    let mut response = vec![0u8; 36];
-    let n = tokio::time::timeout(
+
+    let response = tokio::time::timeout(
         tokio::time::Duration::from_secs(5),
-        stream.read(&mut response)
+        secure.recv_frame()
     ).await.map_err(|_| "Read timeout")??;
-    
-    response.truncate(n);
-    let response_str = String::from_utf8_lossy(&response);
-    
-    if response_str == "ERR" {
-        return Err("Remote error".into());
+
+    let (ack, _): (protocol::Ack, Vec<u8>) = protocol::decode_response(&response)?;
+    if !ack.ok {
+        return Err(format!("Remote error: {}", ack.message).into());
     }
-    
-    Ok(response_str.to_string())
+
+    Ok(ack.message)
 }
 
-async fn download(file_id: &str, output: &str, db: Arc<Database>) -> Result<()> {
-    let manifest = db.get_manifest(file_id)?;
+/// Fetches, integrity-checks, and Reed-Solomon-reconstructs one chunk's
+/// plaintext from its replica shards, decrypting and decompressing it and
+/// verifying the result against `chunk_info.digest`. Takes the manifest
+/// slice covering a single chunk rather than a whole `Manifest` so it can
+/// be shared by `download` (which already has the full manifest) and the
+/// `MapTask` handler (which only gets shipped the one chunk it needs).
+async fn reconstruct_chunk(
+    ctx: &NodeContext,
+    encryption_key: &[u8],
+    chunk_info: &ChunkInfo,
+    chunk_shards: &[ShardLocation],
+) -> Result<Vec<u8>> {
+    let mut shards: Vec<Option<Vec<u8>>> = vec![None; DATA_SHARDS + PARITY_SHARDS];
+    let mut count = 0;
+
+    // `chunk_shards` holds the replicas for each shard index in the
+    // closeness order `upload` placed them in; stop trying a shard index's
+    // remaining replicas as soon as one of them succeeds, and fall through
+    // to the next replica when one is unreachable.
+    for shard_loc in chunk_shards {
+        if count >= DATA_SHARDS {
+            break;
+        }
+        if shards[shard_loc.shard_index].is_some() {
+            continue;
+        }
+
+        match fetch_shard(ctx, &shard_loc.device_address, &shard_loc.shard_id).await {
+            Ok(data) => {
+                let expected = shard_tag(encryption_key, chunk_info.chunk_index, shard_loc.shard_index, &data);
+                if expected != shard_loc.tag {
+                    eprintln!(
+                        "Shard {} failed integrity check, trying next replica",
+                        shard_loc.shard_index
+                    );
+                    continue;
+                }
+                shards[shard_loc.shard_index] = Some(data);
+                count += 1;
+            }
+            Err(e) => eprintln!(
+                "Fetch shard {} from {} failed: {}, trying next replica",
+                shard_loc.shard_index, shard_loc.device_address, e
+            ),
+        }
+    }
+
+    if count < DATA_SHARDS {
+        return Err(format!("Not enough shards: {}/{}", count, DATA_SHARDS).into());
+    }
+
+    // Leave genuinely missing/failed shard slots as `None` so
+    // `rs.reconstruct` actually has holes to fill in from parity - feeding it
+    // a fake zero buffer instead would leave a corrupted data shard as
+    // garbage rather than recovering it, making parity a no-op.
+    shards.iter().find_map(|s| s.as_ref().map(|d| d.len())).ok_or("No valid shard")?;
+    let mut shard_vec = shards;
+
+    let rs = ReedSolomon::new(DATA_SHARDS, PARITY_SHARDS)?;
+    rs.reconstruct(&mut shard_vec)?;
+
+    let mut encrypted = Vec::new();
+    for shard_opt in shard_vec.iter().take(DATA_SHARDS) {
+        if let Some(shard) = shard_opt {
+            encrypted.extend_from_slice(shard);
+        }
+    }
+
+    // Trim padding back to the original encrypted size.
+    encrypted.truncate(chunk_info.encrypted_size);
+
+    let compressed = decrypt_chunk(&encrypted, encryption_key, &chunk_info.nonce)?;
+    let chunk = lz4::block::decompress(&compressed, Some(10 * 1024 * 1024))?;
+
+    if Sha256::digest(&chunk).to_vec() != chunk_info.digest {
+        return Err(format!("Chunk {} failed digest verification", chunk_info.chunk_index).into());
+    }
+
+    Ok(chunk)
+}
+
+async fn download(file_id: &str, output: &str, ctx: Arc<NodeContext>) -> Result<()> {
+    let manifest = ctx.db.get_manifest(file_id)?;
     let mut file_data = Vec::new();
-    
+
     println!("Downloading: {}", manifest.original_name);
 
     for chunk_idx in 0..manifest.chunk_count {
         print!("Chunk {}/{}... ", chunk_idx + 1, manifest.chunk_count);
-        
-        let chunk_shards: Vec<_> = manifest.shard_map.iter()
+
+        let chunk_shards: Vec<ShardLocation> = manifest.shard_map.iter()
             .filter(|s| s.chunk_index == chunk_idx)
+            .cloned()
             .collect();
-        
-        let mut shards: Vec<Option<Vec<u8>>> = vec![None; DATA_SHARDS + PARITY_SHARDS];
-        let mut count = 0;
-        
-        for shard_loc in &chunk_shards {
-            if count >= DATA_SHARDS {
-                break;
+        let chunk_info = manifest.chunks.iter()
+            .find(|c| c.chunk_index == chunk_idx)
+            .ok_or("Chunk info not found")?;
+
+        match reconstruct_chunk(&ctx, &manifest.encryption_key, chunk_info, &chunk_shards).await {
+            Ok(chunk) => {
+                file_data.extend(chunk);
+                println!("✓");
             }
-            
-            match fetch_shard(&shard_loc.device_address, &shard_loc.shard_id).await {
-                Ok(data) => {
-                    shards[shard_loc.shard_index] = Some(data);
-                    count += 1;
-                }
-                Err(e) => eprintln!("Fetch shard {} failed: {}", shard_loc.shard_index, e),
+            Err(e) => {
+                println!("✗");
+                return Err(e);
             }
         }
-        
-        if count < DATA_SHARDS {
-            println!("✗");
-            return Err(format!("Not enough shards: {}/{}", count, DATA_SHARDS).into());
-        }
-        
-        let shard_size = shards.iter()
-            .find_map(|s| s.as_ref().map(|d| d.len()))
-            .ok_or("No valid shard")?;
+    }
 
-        let mut shard_vec: Vec<Option<Vec<u8>>> = shards.into_iter()
-            .map(|s| s.or_else(|| Some(vec![0u8; shard_size])))
-            .collect();
+    file_data.truncate(manifest.file_size);
 
-        let rs = ReedSolomon::new(DATA_SHARDS, PARITY_SHARDS)?;
-        rs.reconstruct(&mut shard_vec)?;
-        
-        let mut encrypted = Vec::new();
-        for shard_opt in shard_vec.iter().take(DATA_SHARDS) {
-            if let Some(shard) = shard_opt {
-                encrypted.extend_from_slice(shard);
-            }
-        }
-        
-        // Get chunk info to know the original encrypted size (before padding)
-        let chunk_info = manifest.chunks.iter()
-            .find(|c| c.chunk_index == chunk_idx)
-            .ok_or("Chunk info not found")?;
-        
-        // Trim padding
-        encrypted.truncate(chunk_info.encrypted_size);
-        
-        let compressed = decrypt_chunk(&encrypted, &manifest.encryption_key, &chunk_info.nonce)?;
-        let chunk = lz4::block::decompress(&compressed, Some(10 * 1024 * 1024))?;
-        file_data.extend(chunk);
-        println!("✓");
-        
+    if Sha256::digest(&file_data).to_vec() != manifest.file_digest {
+        return Err("Whole-file digest verification failed".into());
     }
-    
-    file_data.truncate(manifest.file_size);
+
     fs::write(output, &file_data)?;
-    
+
     println!("\n✓ Download complete: {}", output);
     Ok(())
 }
 
-async fn fetch_shard(addr: &str, shard_id: &str) -> Result<Vec<u8>> {
-    let mut stream = TcpStream::connect(addr).await?;
-    
-    let request = format!("GET:{}\n", shard_id);
-    stream.write_all(request.as_bytes()).await?;
-    
-    let mut data = Vec::new();
-    stream.read_to_end(&mut data).await?;
-    
-    if data == b"ERR" {
-        return Err("Shard not found".into());
+/// Downloads the `num_versions` most recent versions of a logical file
+/// name, one per version timestamp suffixed onto `output_prefix`.
+async fn get_versions(name: &str, num_versions: usize, output_prefix: &str, ctx: Arc<NodeContext>) -> Result<()> {
+    let versions = ctx.db.list_versions(name)?;
+    if versions.is_empty() {
+        return Err(format!("No versions found for {}", name).into());
     }
-    
+
+    for entry in versions.into_iter().take(num_versions) {
+        let output = format!("{}.{}", output_prefix, entry.timestamp);
+        println!("Fetching version {} (file_id {})...", entry.timestamp, entry.file_id);
+        download(&entry.file_id, &output, Arc::clone(&ctx)).await?;
+    }
+
+    Ok(())
+}
+
+async fn fetch_shard(ctx: &NodeContext, addr: &str, shard_id: &str) -> Result<Vec<u8>> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut secure = SecureStream::initiate(stream, &ctx.identity, &ctx.trusted).await?;
+
+    secure.send_frame(&protocol::encode_fetch(shard_id)?).await?;
+
+    let response = secure.recv_frame().await?;
+    let (ack, data): (protocol::Ack, Vec<u8>) = protocol::decode_response(&response)?;
+    if !ack.ok {
+        return Err(format!("Shard not found: {}", ack.message).into());
+    }
+
     Ok(data)
 }
 
+async fn delete_shard(ctx: &NodeContext, device_address: &str, shard_id: &str) -> Result<()> {
+    let stream = TcpStream::connect(device_address).await?;
+    let mut secure = SecureStream::initiate(stream, &ctx.identity, &ctx.trusted).await?;
+
+    secure.send_frame(&protocol::encode_delete(shard_id)?).await?;
+
+    let response = secure.recv_frame().await?;
+    let (ack, _): (protocol::Ack, Vec<u8>) = protocol::decode_response(&response)?;
+    if !ack.ok {
+        return Err(format!("Remote error: {}", ack.message).into());
+    }
+
+    Ok(())
+}
+
+/// Best-effort garbage collection for versions `record_version` just
+/// evicted: sends a `DELETE` for every shard the evicted manifests pointed
+/// at, so retention actually bounds storage on the peers holding those
+/// shards instead of leaking them forever. Failures are logged and
+/// otherwise ignored - a peer that's gone is no longer using the space
+/// anyway, and a stray undeleted shard doesn't compromise anything.
+async fn gc_evicted_shards(ctx: &NodeContext, evicted: Vec<Manifest>) {
+    for manifest in evicted {
+        for loc in &manifest.shard_map {
+            if let Err(e) = delete_shard(ctx, &loc.device_address, &loc.shard_id).await {
+                eprintln!("GC: failed to delete shard {} on {}: {}", loc.shard_id, loc.device_address, e);
+            }
+        }
+    }
+}
+
 fn encrypt_chunk(data: &[u8], key: &[u8; 32]) -> Result<(Vec<u8>, Vec<u8>)> {
     let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
     let nonce_bytes: [u8; 12] = rand::thread_rng().gen();
@@ -677,6 +1190,19 @@ fn encrypt_chunk(data: &[u8], key: &[u8; 32]) -> Result<(Vec<u8>, Vec<u8>)> {
     Ok((encrypted, nonce_bytes.to_vec()))
 }
 
+/// Keyed integrity tag for one Reed-Solomon shard, so a corrupted or
+/// tampered shard can be told apart from a simply-missing one before
+/// `reconstruct` runs. Keyed off the manifest's encryption key plus the
+/// chunk/shard coordinates so a tag can't be replayed onto a different shard.
+fn shard_tag(manifest_key: &[u8], chunk_index: usize, shard_index: usize, data: &[u8]) -> Vec<u8> {
+    let mut key_material = Vec::with_capacity(manifest_key.len() + 16);
+    key_material.extend_from_slice(manifest_key);
+    key_material.extend_from_slice(&chunk_index.to_be_bytes());
+    key_material.extend_from_slice(&shard_index.to_be_bytes());
+    let derived_key = blake3::hash(&key_material);
+    blake3::keyed_hash(derived_key.as_bytes(), data).as_bytes().to_vec()
+}
+
 fn decrypt_chunk(data: &[u8], key: &[u8], nonce_bytes: &[u8]) -> Result<Vec<u8>> {
     if key.len() != 32 || nonce_bytes.len() != 12 {
         return Err("Invalid key or nonce size".into());
@@ -694,29 +1220,52 @@ async fn main() -> Result<()> {
     if args.len() == 1 {
         // Daemon mode - needs database
         let device_id = device_id();
-        let db = Arc::new(Database::new(&device_id)?);
+        let ctx = Arc::new(NodeContext::new(&device_id)?);
         println!("Device: {}", device_id);
-        
+
         // Register with server
         register_with_server(&device_id).await?;
-        
+
         // Start mDNS
-        start_mdns_advertise(device_id).await?;
-        
-        listen(db).await?;
+        start_mdns_advertise(device_id.clone()).await?;
+
+        // Run the SWIM failure detector in the background so `devices` and
+        // `upload` have a live-vs-dead view of the network, not just a
+        // static mDNS/beacon snapshot.
+        tokio::spawn(membership::run(Arc::clone(&ctx)));
+
+        // Best-effort WAN reachability: map the listen port through UPnP and
+        // publish a beacon so a remote peer can dial in without mDNS.
+        let port = get_listen_port();
+        let mut reachable: Vec<std::net::SocketAddr> =
+            vec![format!("{}:{}", get_local_ip(), port).parse()?];
+        if let Some(external) = beacon::map_external_port(port) {
+            reachable.push(external);
+        }
+        let static_key_hex = hex::encode(ctx.identity.public_key().as_bytes());
+        let beacon_str = beacon::encode_beacon(&device_id, &static_key_hex, &reachable)?;
+        if let Ok(path) = std::env::var("BEACON_FILE") {
+            if let Err(e) = beacon::write_beacon_file(&path, &beacon_str) {
+                eprintln!("Failed to publish beacon to {}: {}", path, e);
+            }
+        } else {
+            println!("Beacon (share with remote peers): {}", beacon_str);
+        }
+
+        listen(ctx).await?;
     } else {
         // CLI commands - use port-specific database to avoid conflicts
         let port = get_listen_port();
         let cli_id = format!("cli_{}", port);
-        
+
         match args[1].as_str() {
             "upload" => {
                 if args.len() < 3 {
                     eprintln!("Usage: vishwarupa upload <file>");
                     std::process::exit(1);
                 }
-                let db = Arc::new(Database::new(&cli_id)?);
-                let file_id = upload(&args[2], db).await?;
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let file_id = upload(&args[2], ctx).await?;
                 println!("File ID: {}", file_id);
             }
             "download" => {
@@ -724,29 +1273,222 @@ async fn main() -> Result<()> {
                     eprintln!("Usage: vishwarupa download <file_id> <output>");
                     std::process::exit(1);
                 }
-                let db = Arc::new(Database::new(&cli_id)?);
-                download(&args[2], &args[3], db).await?;
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                download(&args[2], &args[3], ctx).await?;
             }
             "list" => {
-                let db = Arc::new(Database::new(&cli_id)?);
-                let files = db.list_files()?;
-                if files.is_empty() {
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let named = ctx.db.list_named_files()?;
+                if named.is_empty() {
                     println!("No files stored");
                 } else {
                     println!("Files:");
-                    for file_id in files {
-                        if let Ok(manifest) = db.get_manifest(&file_id) {
-                            println!("  {} - {} ({} bytes)", 
-                                file_id, manifest.original_name, manifest.file_size);
+                    for (name, version_count) in named {
+                        let plural = if version_count == 1 { "" } else { "s" };
+                        let versions = ctx.db.list_versions(&name)?;
+                        match versions.first().and_then(|latest| ctx.db.get_manifest(&latest.file_id).ok().map(|m| (latest, m))) {
+                            Some((latest, manifest)) => {
+                                let status = if manifest.deleted { " (deleted)" } else { "" };
+                                println!("  {} - {} ({} bytes) [{} version{}]{}",
+                                    latest.file_id, name, manifest.file_size, version_count, plural, status);
+                            }
+                            None => println!("  {} [{} version{}]", name, version_count, plural),
+                        }
+                    }
+                }
+            }
+            "get-versions" => {
+                if args.len() < 5 {
+                    eprintln!("Usage: vishwarupa get-versions <name> <num_versions> <local_path>");
+                    std::process::exit(1);
+                }
+                let num_versions: usize = args[3].parse().map_err(|_| "num_versions must be a number")?;
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                get_versions(&args[2], num_versions, &args[4], ctx).await?;
+            }
+            "ls" => {
+                if args.len() < 3 {
+                    eprintln!("Usage: vishwarupa ls <file_id>");
+                    std::process::exit(1);
+                }
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let manifest = ctx.db.get_manifest(&args[2])?;
+                println!("Placement for {} ({}):", args[2], manifest.original_name);
+                for chunk_idx in 0..manifest.chunk_count {
+                    let holders: Vec<String> = manifest.shard_map.iter()
+                        .filter(|s| s.chunk_index == chunk_idx)
+                        .map(|s| format!("{} (shard {})", s.device_id, s.shard_index))
+                        .collect();
+                    println!("  chunk {}: {}", chunk_idx, holders.join(", "));
+                }
+            }
+            "store" => {
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let inventory = local_shard_inventory(&ctx)?;
+                if inventory.is_empty() {
+                    println!("Not holding any shards");
+                } else {
+                    println!("Locally held shards:");
+                    for metadata in inventory {
+                        println!("  file {} chunk {} shard {}", metadata.file_id, metadata.chunk_index, metadata.shard_index);
+                    }
+                }
+            }
+            "verify" => {
+                if args.len() < 3 {
+                    eprintln!("Usage: vishwarupa verify <file_id>");
+                    std::process::exit(1);
+                }
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let mut manifest = ctx.db.get_manifest(&args[2])?;
+                let payload = manifest_signing_payload(&manifest.file_id, &manifest.file_digest, &manifest.chunks);
+
+                println!("Verifying manifest for {} ({})", args[2], manifest.original_name);
+                println!("  file digest:  {}", hex::encode(&manifest.file_digest));
+                println!("  chunk digests: {} recorded", manifest.chunks.len());
+
+                // Actually re-fetch and reconstruct every chunk rather than
+                // trusting the manifest's self-reported digests: a forged
+                // file_digest/chunk digest must fail here, not sail through
+                // on the strength of its own say-so.
+                let mut digests_ok = true;
+                let mut file_data = Vec::new();
+                for chunk_idx in 0..manifest.chunk_count {
+                    let chunk_info = match manifest.chunks.iter().find(|c| c.chunk_index == chunk_idx) {
+                        Some(c) => c.clone(),
+                        None => {
+                            println!("  ✗ chunk {} has no recorded info", chunk_idx);
+                            digests_ok = false;
+                            continue;
+                        }
+                    };
+                    let chunk_shards: Vec<ShardLocation> =
+                        manifest.shard_map.iter().filter(|s| s.chunk_index == chunk_idx).cloned().collect();
+                    match reconstruct_chunk(&ctx, &manifest.encryption_key, &chunk_info, &chunk_shards).await {
+                        Ok(chunk) => {
+                            println!("  ✓ chunk {} reconstructed and digest matches", chunk_idx);
+                            file_data.extend_from_slice(&chunk);
+                        }
+                        Err(e) => {
+                            println!("  ✗ chunk {} failed verification: {}", chunk_idx, e);
+                            digests_ok = false;
+                        }
+                    }
+                }
+                if digests_ok && Sha256::digest(&file_data).to_vec() != manifest.file_digest {
+                    println!("  ✗ reconstructed file digest does not match manifest's file_digest");
+                    digests_ok = false;
+                }
+
+                let mut signatures_ok = true;
+                let mut attestors = Vec::new();
+                for (signer_id, signature) in &manifest.signatures {
+                    match ctx.db.get_signer_key(signer_id)? {
+                        Some(public_key) if signing::verify(&public_key, &payload, signature) => {
+                            attestors.push(signer_id.clone());
+                        }
+                        Some(_) => {
+                            println!("  ✗ signature from {} does not match its recorded key", signer_id);
+                            signatures_ok = false;
                         }
+                        None => println!("  ? {} signed but its signing key is unknown here", signer_id),
                     }
                 }
+
+                println!("Attested by: {}", if attestors.is_empty() { "nobody (yet)".to_string() } else { attestors.join(", ") });
+
+                if !digests_ok || !signatures_ok {
+                    eprintln!("✗ verification failed, refusing to cross-sign");
+                    std::process::exit(1);
+                }
+
+                if !manifest.signatures.iter().any(|(id, _)| id == &cli_id) {
+                    let our_signature = signing::sign(&ctx.signing_key, &payload);
+                    manifest.signatures.push((cli_id.clone(), our_signature));
+                    ctx.db.store_manifest(&manifest)?;
+                    println!("✓ cross-signed by {}", cli_id);
+                }
+            }
+            "watch" => {
+                if args.len() < 3 {
+                    eprintln!("Usage: vishwarupa watch <dir>");
+                    std::process::exit(1);
+                }
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                watch::run(&args[2], ctx).await?;
             }
             "devices" => {
-                let devices = discover_devices().await?;
-                println!("Devices: {}", devices.len());
-                for d in devices {
-                    println!("  {} @ {}:{}", d.device_id, d.address, d.port);
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let devices = discover_devices(&ctx.trusted).await?;
+                println!("Discovered {} devices", devices.len());
+
+                ctx.seed_peers(&devices);
+                membership::sync_from_peers(&ctx, &devices).await;
+
+                println!("Membership:");
+                for (device, state) in ctx.membership.all_members() {
+                    println!("  {} @ {}:{} [{:?}]", device.device_id, device.address, device.port, state);
+                }
+                println!("Live members: {}", ctx.membership.live_members().len());
+
+                let routed = ctx.routing_table.all_peers();
+                println!("DHT routing table: {} peers", routed.len());
+                for p in routed {
+                    println!("  {} ({}) @ {}:{}", hex::encode(p.id), p.device.device_id, p.device.address, p.device.port);
+                }
+            }
+            "locate" => {
+                if args.len() < 3 {
+                    eprintln!("Usage: vishwarupa locate <file_id>");
+                    std::process::exit(1);
+                }
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let devices = discover_devices(&ctx.trusted).await?;
+                ctx.seed_peers(&devices);
+                membership::sync_from_peers(&ctx, &devices).await;
+
+                match dht::iterative_find_value(&ctx, &ctx.routing_table, dht::content_id(&args[2])).await {
+                    Some(providers) => {
+                        println!("Providers for {}:", args[2]);
+                        for p in providers {
+                            println!("  {} @ {}:{}", p.device_id, p.address, p.port);
+                        }
+                    }
+                    None => println!("No providers found for {}", args[2]),
+                }
+            }
+            "map-reduce" => {
+                if args.len() < 4 {
+                    eprintln!("Usage: vishwarupa map-reduce <file_id> <task>");
+                    eprintln!("Tasks: wordcount, linecount, bytehist");
+                    std::process::exit(1);
+                }
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let job_id = mapreduce::run(&args[2], &args[3], ctx).await?;
+                println!("Job ID: {}", job_id);
+            }
+            "jobs" => {
+                let ctx = Arc::new(NodeContext::new(&cli_id)?);
+                let jobs = ctx.db.list_jobs()?;
+                if jobs.is_empty() {
+                    println!("No map-reduce jobs recorded");
+                } else {
+                    println!("Jobs:");
+                    for job in jobs {
+                        let p50 = mapreduce::percentile(&job.task_durations_ms, 50.0);
+                        let p90 = mapreduce::percentile(&job.task_durations_ms, 90.0);
+                        let p99 = mapreduce::percentile(&job.task_durations_ms, 99.0);
+                        println!(
+                            "  {} - {} on {} [{}/{} done, {} failed, {} rescheduled]",
+                            job.job_id, job.task, job.file_id, job.completed_tasks, job.total_tasks,
+                            job.failed_tasks, job.rescheduled_tasks,
+                        );
+                        println!(
+                            "    task time p50={}ms p90={}ms p99={}ms, output={}",
+                            p50, p90, p99,
+                            job.output_file_id.as_deref().unwrap_or("(none)"),
+                        );
+                    }
                 }
             }
             "id" => {
@@ -754,7 +1496,7 @@ async fn main() -> Result<()> {
                 println!("{}", device_id);
             }
             _ => {
-                eprintln!("Commands: upload, download, list, devices, id");
+                eprintln!("Commands: upload, download, list, get-versions, ls, store, verify, watch, devices, locate, map-reduce, jobs, id");
                 std::process::exit(1);
             }
         }
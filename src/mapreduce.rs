@@ -0,0 +1,287 @@
+// Data-local map/reduce over already-sharded files. A chunk's shards are
+// already spread across the peers that hold them, so instead of pulling a
+// whole file back to one machine, a coordinator ships each chunk's tiny
+// manifest slice (encryption key, chunk info, shard locations) to a peer
+// that already holds one of its replicas; that peer reconstructs the
+// chunk itself via `reconstruct_chunk` and runs the map task locally. The
+// resulting key/count pairs are shuffled by key hash to a handful of live
+// reducer peers, merged, and the combined output is written back through
+// the ordinary `upload` pipeline as a new manifest. Job stats (per-task
+// durations, retries, the output file) are persisted so a later `jobs`
+// invocation can report on them - jobs run to completion within one CLI
+// invocation, so there's no live daemon state, only this history.
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use crate::handshake::SecureStream;
+use crate::{dht, protocol, Device, NodeContext, Result};
+
+const MAP_TIMEOUT: Duration = Duration::from_secs(30);
+const REDUCE_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_REDUCERS: usize = 4;
+
+/// A finished job's stats, keyed by `job_id` in the database so `jobs` can
+/// list job history across CLI invocations.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JobRecord {
+    pub job_id: String,
+    pub file_id: String,
+    pub task: String,
+    pub total_tasks: usize,
+    pub completed_tasks: usize,
+    pub failed_tasks: usize,
+    pub rescheduled_tasks: usize,
+    pub task_durations_ms: Vec<u64>,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub output_file_id: Option<String>,
+}
+
+/// The `pct` (0-100) percentile of a set of millisecond task durations,
+/// nearest-rank: sorted ascending, index = ceil(pct/100 * n) - 1.
+pub fn percentile(durations_ms: &[u64], pct: f64) -> u64 {
+    if durations_ms.is_empty() {
+        return 0;
+    }
+    let mut sorted = durations_ms.to_vec();
+    sorted.sort_unstable();
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    let idx = rank.saturating_sub(1).min(sorted.len() - 1);
+    sorted[idx]
+}
+
+/// Runs one of the built-in map tasks against a reconstructed chunk's
+/// plaintext. Map tasks are a fixed set rather than arbitrary user code,
+/// since nothing in this crate embeds a scripting engine.
+pub fn apply_map(task: &str, chunk: &[u8]) -> Result<Vec<(String, u64)>> {
+    match task {
+        "wordcount" => {
+            let mut counts: HashMap<String, u64> = HashMap::new();
+            for word in String::from_utf8_lossy(chunk).split_whitespace() {
+                *counts.entry(word.to_lowercase()).or_insert(0) += 1;
+            }
+            Ok(counts.into_iter().collect())
+        }
+        "linecount" => Ok(vec![("lines".to_string(), String::from_utf8_lossy(chunk).lines().count() as u64)]),
+        "bytehist" => {
+            let mut counts: HashMap<String, u64> = HashMap::new();
+            for b in chunk {
+                *counts.entry(format!("byte_{}", b)).or_insert(0) += 1;
+            }
+            Ok(counts.into_iter().collect())
+        }
+        other => Err(format!("Unknown map-reduce task '{}'", other).into()),
+    }
+}
+
+/// Sums counts for identical keys, the reduce side of every built-in task.
+pub fn reduce_sum(pairs: Vec<(String, u64)>) -> Vec<(String, u64)> {
+    let mut totals: HashMap<String, u64> = HashMap::new();
+    for (key, count) in pairs {
+        *totals.entry(key).or_insert(0) += count;
+    }
+    let mut out: Vec<(String, u64)> = totals.into_iter().collect();
+    out.sort_by(|a, b| a.0.cmp(&b.0));
+    out
+}
+
+/// Which of `reducer_count` reducers a key's partition shuffles to.
+fn partition_index(key: &str, reducer_count: usize) -> usize {
+    (blake3::hash(key.as_bytes()).as_bytes()[0] as usize) % reducer_count
+}
+
+/// One-shot RPC that sends a `MapTask`/`Reduce` request and decodes the
+/// `Ack`-plus-raw-JSON response both of those opcodes' handlers send back,
+/// returning the key/count pairs on success.
+async fn mapreduce_rpc(ctx: &NodeContext, device: &Device, request: Vec<u8>) -> Result<Vec<(String, u64)>> {
+    let addr = format!("{}:{}", device.address, device.port);
+    let stream = TcpStream::connect(&addr).await?;
+    let mut secure = SecureStream::initiate(stream, &ctx.identity, &ctx.trusted).await?;
+    secure.send_frame(&request).await?;
+    let response = secure.recv_frame().await?;
+    let (ack, raw): (protocol::Ack, Vec<u8>) = protocol::decode_response(&response)?;
+    if !ack.ok {
+        return Err(format!("Remote error: {}", ack.message).into());
+    }
+    Ok(serde_json::from_slice(&raw)?)
+}
+
+/// Coordinates one map-reduce job over `file_id`'s chunks: schedules a map
+/// task per chunk data-locally on one of its live replica holders
+/// (rescheduling onto the next replica if the preferred holder is dead or
+/// unreachable), shuffles the resulting pairs to reducer peers by key
+/// hash, and writes the merged output back as a new manifest.
+pub async fn run(file_id: &str, task: &str, ctx: Arc<NodeContext>) -> Result<String> {
+    let manifest = ctx.db.get_manifest(file_id)?;
+    if manifest.deleted {
+        return Err("Cannot run a job against a deleted file version".into());
+    }
+
+    let devices = crate::discover_devices(&ctx.trusted).await?;
+    ctx.seed_peers(&devices);
+    // This coordinator's own `Membership` is brand new - pull in whatever
+    // a running daemon has already determined via SWIM instead of treating
+    // every freshly-seeded peer as alive, so dead-worker rescheduling below
+    // actually has something to reschedule away from.
+    crate::membership::sync_from_peers(&ctx, &devices).await;
+
+    let job_id = Uuid::new_v4().to_string();
+    let started_at = crate::now_unix();
+    let total_tasks = manifest.chunk_count;
+    let mut durations_ms = Vec::new();
+    let mut completed = 0usize;
+    let mut failed = 0usize;
+    let mut rescheduled = 0usize;
+    let mut all_pairs: Vec<(String, u64)> = Vec::new();
+
+    println!("Job {}: scheduling {} map task(s) for {}", job_id, total_tasks, manifest.original_name);
+
+    for chunk_idx in 0..manifest.chunk_count {
+        let chunk_info = manifest.chunks.iter()
+            .find(|c| c.chunk_index == chunk_idx)
+            .ok_or("Chunk info not found")?
+            .clone();
+        let chunk_shards: Vec<_> = manifest.shard_map.iter()
+            .filter(|s| s.chunk_index == chunk_idx)
+            .cloned()
+            .collect();
+
+        // Candidate workers: whichever peers hold a replica of this chunk,
+        // in the same closeness order `upload` placed them in - data
+        // locality first, falling through to the next replica holder if
+        // the preferred one turns out dead or unreachable.
+        let mut candidates: Vec<String> = Vec::new();
+        for loc in &chunk_shards {
+            if !candidates.contains(&loc.device_id) {
+                candidates.push(loc.device_id.clone());
+            }
+        }
+
+        let live_ids: std::collections::HashSet<_> =
+            ctx.membership.live_members().into_iter().map(|d| d.device_id).collect();
+
+        let mut result = None;
+        for device_id in &candidates {
+            if !live_ids.contains(device_id) {
+                println!("  chunk {}: {} is dead, rescheduling onto next replica", chunk_idx, device_id);
+                rescheduled += 1;
+                continue;
+            }
+            let Some(device) = devices.iter().find(|d| &d.device_id == device_id) else { continue };
+
+            let request = protocol::encode_map_task(
+                file_id,
+                chunk_idx,
+                task,
+                &manifest.encryption_key,
+                chunk_info.clone(),
+                chunk_shards.clone(),
+            )?;
+
+            let started = Instant::now();
+            match tokio::time::timeout(MAP_TIMEOUT, mapreduce_rpc(&ctx, device, request)).await {
+                Ok(Ok(pairs)) => {
+                    durations_ms.push(started.elapsed().as_millis() as u64);
+                    result = Some(pairs);
+                    break;
+                }
+                Ok(Err(e)) => {
+                    println!("  chunk {}: task on {} failed ({}), rescheduling", chunk_idx, device_id, e);
+                    rescheduled += 1;
+                }
+                Err(_) => {
+                    println!("  chunk {}: task on {} timed out, rescheduling", chunk_idx, device_id);
+                    rescheduled += 1;
+                }
+            }
+        }
+
+        match result {
+            Some(pairs) => {
+                completed += 1;
+                all_pairs.extend(pairs);
+            }
+            None => {
+                failed += 1;
+                eprintln!("  chunk {}: no live replica could complete the map task", chunk_idx);
+            }
+        }
+    }
+
+    if completed == 0 {
+        return Err("No map tasks completed".into());
+    }
+
+    println!("Map phase done: {}/{} task(s), {} rescheduled", completed, total_tasks, rescheduled);
+
+    // Shuffle: partition the pairs by key hash across a handful of live
+    // reducer peers, falling back to reducing a partition locally if its
+    // reducer is unreachable.
+    let reducers: Vec<Device> = {
+        let live = ctx.membership.live_members();
+        dht::rank_by_closeness(&live, dht::content_id(&job_id)).into_iter().take(DEFAULT_REDUCERS).collect()
+    };
+
+    let mut final_pairs = if reducers.is_empty() {
+        reduce_sum(all_pairs)
+    } else {
+        let reducer_count = reducers.len();
+        let mut partitions: Vec<Vec<(String, u64)>> = vec![Vec::new(); reducer_count];
+        for pair in all_pairs {
+            let idx = partition_index(&pair.0, reducer_count);
+            partitions[idx].push(pair);
+        }
+
+        let mut merged = Vec::new();
+        for (reducer, partition) in reducers.iter().zip(partitions.into_iter()) {
+            if partition.is_empty() {
+                continue;
+            }
+            let request = protocol::encode_reduce(partition.clone())?;
+            match tokio::time::timeout(REDUCE_TIMEOUT, mapreduce_rpc(&ctx, reducer, request)).await {
+                Ok(Ok(reduced)) => merged.extend(reduced),
+                _ => {
+                    println!("  reducer {} unreachable, reducing its partition locally", reducer.device_id);
+                    merged.extend(reduce_sum(partition));
+                }
+            }
+        }
+        merged
+    };
+    final_pairs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let output_path = format!("mapreduce_{}.txt", job_id);
+    let mut output = String::new();
+    for (key, count) in &final_pairs {
+        output.push_str(&format!("{}\t{}\n", key, count));
+    }
+    fs::write(&output_path, &output)?;
+    let upload_result = crate::upload(&output_path, Arc::clone(&ctx)).await;
+    let _ = fs::remove_file(&output_path);
+    let output_file_id = upload_result?;
+
+    let record = JobRecord {
+        job_id: job_id.clone(),
+        file_id: file_id.to_string(),
+        task: task.to_string(),
+        total_tasks,
+        completed_tasks: completed,
+        failed_tasks: failed,
+        rescheduled_tasks: rescheduled,
+        task_durations_ms: durations_ms,
+        started_at,
+        finished_at: crate::now_unix(),
+        output_file_id: Some(output_file_id.clone()),
+    };
+    ctx.db.record_job(&record)?;
+
+    println!("\n✓ Job {} complete, output written as {}", job_id, output_file_id);
+    Ok(job_id)
+}
@@ -0,0 +1,349 @@
+// SWIM-style failure detection layered on top of mDNS/beacon discovery.
+// `discover_devices()` only ever gives a static snapshot, so a peer that
+// went offline mid-session keeps looking reachable forever; this module
+// gives every node an evolving opinion of who is actually up. Each protocol
+// period a node pings one random member directly; on timeout it asks a few
+// other members to ping it indirectly; only if both fail does the target
+// become `suspect`, then `dead` once it's sat suspect past a timeout.
+// Incarnation numbers let a node outrun its own suspicion by re-announcing
+// itself `alive` at a higher incarnation, and every ping/ack piggybacks the
+// handful of most recent state changes so they spread without a dedicated
+// broadcast.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use tokio::net::TcpStream;
+
+use crate::handshake::SecureStream;
+use crate::{protocol, Device, NodeContext, Result};
+
+pub use protocol::{MemberState, MemberUpdate};
+
+const PROTOCOL_PERIOD: Duration = Duration::from_secs(2);
+pub const PING_TIMEOUT: Duration = Duration::from_millis(800);
+const INDIRECT_PING_TIMEOUT: Duration = Duration::from_millis(1200);
+const INDIRECT_PROBES: usize = 3;
+const SUSPECT_TIMEOUT: Duration = Duration::from_secs(6);
+const GOSSIP_FANOUT: usize = 6;
+
+struct MemberRecord {
+    device: Device,
+    incarnation: u64,
+    state: MemberState,
+    since: Instant,
+}
+
+fn rank(state: &MemberState) -> u8 {
+    match state {
+        MemberState::Alive => 0,
+        MemberState::Suspect => 1,
+        MemberState::Dead => 2,
+    }
+}
+
+/// Whether an incoming `(incarnation, state)` fact should overwrite what we
+/// currently believe: a strictly higher incarnation always wins, and at
+/// equal incarnation `Dead` beats `Suspect` beats `Alive`.
+fn should_adopt(existing: (u64, &MemberState), incoming: (u64, &MemberState)) -> bool {
+    if incoming.0 != existing.0 {
+        return incoming.0 > existing.0;
+    }
+    rank(incoming.1) > rank(existing.1)
+}
+
+/// This node's view of cluster membership, plus the small backlog of
+/// recent state changes still worth gossiping.
+pub struct Membership {
+    self_id: String,
+    members: Mutex<HashMap<String, MemberRecord>>,
+    gossip: Mutex<VecDeque<MemberUpdate>>,
+}
+
+impl Membership {
+    pub fn new(self_device: Device) -> Self {
+        let self_id = self_device.device_id.clone();
+        let mut members = HashMap::new();
+        members.insert(
+            self_id.clone(),
+            MemberRecord { device: self_device, incarnation: 0, state: MemberState::Alive, since: Instant::now() },
+        );
+        Self { self_id, members: Mutex::new(members), gossip: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Adds newly discovered peers as `Alive` if we've never heard of them;
+    /// leaves existing opinions (including `suspect`/`dead`) untouched.
+    pub fn seed(&self, devices: &[Device]) {
+        let mut members = self.members.lock().unwrap();
+        for device in devices {
+            members.entry(device.device_id.clone()).or_insert_with(|| MemberRecord {
+                device: device.clone(),
+                incarnation: 0,
+                state: MemberState::Alive,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Devices currently believed alive, excluding ourselves - the set
+    /// `upload` should place replicas on.
+    pub fn live_members(&self) -> Vec<Device> {
+        self.members
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|m| m.device.device_id != self.self_id && matches!(m.state, MemberState::Alive))
+            .map(|m| m.device.clone())
+            .collect()
+    }
+
+    /// Every known member alongside its current state, for display.
+    pub fn all_members(&self) -> Vec<(Device, MemberState)> {
+        self.members.lock().unwrap().values().map(|m| (m.device.clone(), m.state.clone())).collect()
+    }
+
+    /// A full snapshot of this table as `MemberUpdate`s (incarnation
+    /// included), suitable for a peer to `ingest` wholesale - backs
+    /// `MembershipQuery`, which lets a brand-new `Membership` (e.g. a
+    /// short-lived CLI invocation) inherit a long-running daemon's
+    /// SWIM-derived view instead of starting from nothing.
+    pub fn snapshot(&self) -> Vec<MemberUpdate> {
+        self.members
+            .lock()
+            .unwrap()
+            .values()
+            .map(|m| MemberUpdate { device: m.device.clone(), incarnation: m.incarnation, state: m.state.clone() })
+            .collect()
+    }
+
+    fn self_device(&self) -> Device {
+        self.members.lock().unwrap().get(&self.self_id).unwrap().device.clone()
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.self_id
+    }
+
+    fn random_probe_target(&self) -> Option<Device> {
+        let members = self.members.lock().unwrap();
+        let mut candidates: Vec<_> = members
+            .values()
+            .filter(|m| m.device.device_id != self.self_id && !matches!(m.state, MemberState::Dead))
+            .map(|m| m.device.clone())
+            .collect();
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.into_iter().next()
+    }
+
+    fn random_helpers(&self, exclude: &str, count: usize) -> Vec<Device> {
+        let members = self.members.lock().unwrap();
+        let mut candidates: Vec<_> = members
+            .values()
+            .filter(|m| {
+                m.device.device_id != self.self_id
+                    && m.device.device_id != exclude
+                    && matches!(m.state, MemberState::Alive)
+            })
+            .map(|m| m.device.clone())
+            .collect();
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.truncate(count);
+        candidates
+    }
+
+    /// Bumps this node's own incarnation and marks it alive - called when a
+    /// gossiped update claims we're suspect or dead, so the refutation
+    /// outranks the stale claim.
+    fn self_refute(&self) -> MemberUpdate {
+        let mut members = self.members.lock().unwrap();
+        let record = members.get_mut(&self.self_id).expect("self entry always present");
+        record.incarnation += 1;
+        record.state = MemberState::Alive;
+        MemberUpdate { device: record.device.clone(), incarnation: record.incarnation, state: MemberState::Alive }
+    }
+
+    fn set_state(&self, device_id: &str, state: MemberState) -> Option<MemberUpdate> {
+        let mut members = self.members.lock().unwrap();
+        let record = members.get_mut(device_id)?;
+        if rank(&record.state) == rank(&state) {
+            return None;
+        }
+        record.state = state.clone();
+        record.since = Instant::now();
+        Some(MemberUpdate { device: record.device.clone(), incarnation: record.incarnation, state })
+    }
+
+    /// Merges one gossiped update, applying a self-refutation if it's about
+    /// us. Returns `true` when it changed local state about a peer (and so
+    /// is worth re-gossiping further).
+    fn merge(&self, update: &MemberUpdate) -> bool {
+        if update.device.device_id == self.self_id {
+            if matches!(update.state, MemberState::Suspect | MemberState::Dead) {
+                let refutation = self.self_refute();
+                self.push_gossip(refutation);
+            }
+            return false;
+        }
+
+        let mut members = self.members.lock().unwrap();
+        let adopt = match members.get(&update.device.device_id) {
+            None => true,
+            Some(existing) => should_adopt((existing.incarnation, &existing.state), (update.incarnation, &update.state)),
+        };
+        if adopt {
+            members.insert(
+                update.device.device_id.clone(),
+                MemberRecord {
+                    device: update.device.clone(),
+                    incarnation: update.incarnation,
+                    state: update.state.clone(),
+                    since: Instant::now(),
+                },
+            );
+        }
+        adopt
+    }
+
+    fn push_gossip(&self, update: MemberUpdate) {
+        let mut gossip = self.gossip.lock().unwrap();
+        gossip.retain(|u| u.device.device_id != update.device.device_id);
+        gossip.push_back(update);
+        while gossip.len() > GOSSIP_FANOUT * 4 {
+            gossip.pop_front();
+        }
+    }
+
+    /// The small batch of most recent updates to piggyback on the next
+    /// ping/ack.
+    pub fn outgoing_updates(&self) -> Vec<MemberUpdate> {
+        self.gossip.lock().unwrap().iter().rev().take(GOSSIP_FANOUT).cloned().collect()
+    }
+
+    /// Promotes any member that has sat `suspect` past the timeout to
+    /// `dead`, returning the updates so the caller can gossip them.
+    fn sweep_expired_suspects(&self) -> Vec<MemberUpdate> {
+        let mut members = self.members.lock().unwrap();
+        let mut expired = Vec::new();
+        for record in members.values_mut() {
+            if matches!(record.state, MemberState::Suspect) && record.since.elapsed() > SUSPECT_TIMEOUT {
+                record.state = MemberState::Dead;
+                record.since = Instant::now();
+                expired.push(MemberUpdate {
+                    device: record.device.clone(),
+                    incarnation: record.incarnation,
+                    state: MemberState::Dead,
+                });
+            }
+        }
+        expired
+    }
+
+    /// Folds a batch of inbound gossip into local state and records
+    /// whichever facts were new so they keep spreading.
+    pub fn ingest(&self, updates: &[MemberUpdate]) {
+        for update in updates {
+            if self.merge(update) {
+                self.push_gossip(update.clone());
+            }
+        }
+    }
+}
+
+async fn rpc_oneshot(ctx: &NodeContext, device: &Device, request: Vec<u8>) -> Result<Vec<u8>> {
+    let addr = format!("{}:{}", device.address, device.port);
+    let stream = TcpStream::connect(&addr).await?;
+    let mut secure = SecureStream::initiate(stream, &ctx.identity, &ctx.trusted).await?;
+    secure.send_frame(&request).await?;
+    secure.recv_frame().await
+}
+
+/// Sends one direct ping to `device`, piggybacking `updates`, and returns
+/// the updates it piggybacked back on its ack.
+pub async fn ping(ctx: &NodeContext, device: &Device, updates: Vec<MemberUpdate>) -> Result<Vec<MemberUpdate>> {
+    let request = protocol::encode_ping(ctx.membership.self_device(), updates)?;
+    let response = rpc_oneshot(ctx, device, request).await?;
+    let (ack, _): (protocol::PingAck, Vec<u8>) = protocol::decode_response(&response)?;
+    Ok(ack.updates)
+}
+
+/// One SWIM protocol period: pick a random member, ping it directly, and on
+/// timeout fall back to indirect pings through a few other members before
+/// marking it suspect.
+async fn probe_once(ctx: &NodeContext) {
+    let Some(target) = ctx.membership.random_probe_target() else { return };
+    let updates = ctx.membership.outgoing_updates();
+
+    if let Ok(Ok(acked)) = tokio::time::timeout(PING_TIMEOUT, ping(ctx, &target, updates.clone())).await {
+        ctx.membership.ingest(&acked);
+        if let Some(u) = ctx.membership.set_state(&target.device_id, MemberState::Alive) {
+            ctx.membership.push_gossip(u);
+        }
+        return;
+    }
+
+    let mut confirmed = false;
+    for helper in ctx.membership.random_helpers(&target.device_id, INDIRECT_PROBES) {
+        let request = match protocol::encode_ping_req(target.clone(), updates.clone()) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        let outcome = tokio::time::timeout(INDIRECT_PING_TIMEOUT, rpc_oneshot(ctx, &helper, request)).await;
+        if let Ok(Ok(response)) = outcome {
+            if let Ok((ack, _)) = protocol::decode_response::<protocol::Ack>(&response) {
+                if ack.ok {
+                    confirmed = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    if confirmed {
+        if let Some(u) = ctx.membership.set_state(&target.device_id, MemberState::Alive) {
+            ctx.membership.push_gossip(u);
+        }
+        return;
+    }
+
+    if let Some(u) = ctx.membership.set_state(&target.device_id, MemberState::Suspect) {
+        println!("Membership: {} is now suspect", target.device_id);
+        ctx.membership.push_gossip(u);
+    }
+}
+
+/// Queries a few candidate peers for their `Membership` table over the wire
+/// and ingests whatever comes back. A CLI invocation's own `Membership` is
+/// brand new and has had zero protocol periods to form an opinion of its
+/// own; this lets it inherit a running daemon's SWIM-derived view instead,
+/// so alive/dead filtering elsewhere in the same call actually means
+/// something. Best-effort: an unreachable or non-responding candidate is
+/// skipped silently, same as a failed direct probe.
+pub async fn sync_from_peers(ctx: &NodeContext, devices: &[Device]) {
+    let request = match protocol::encode_membership_query() {
+        Ok(r) => r,
+        Err(_) => return,
+    };
+    for device in devices.iter().filter(|d| d.device_id != ctx.membership.self_id).take(3) {
+        let outcome = tokio::time::timeout(PING_TIMEOUT, rpc_oneshot(ctx, device, request.clone())).await;
+        if let Ok(Ok(response)) = outcome {
+            if let Ok((snapshot, _)) = protocol::decode_response::<protocol::MembershipSnapshot>(&response) {
+                ctx.membership.ingest(&snapshot.members);
+            }
+        }
+    }
+}
+
+/// Background task: runs the SWIM protocol period forever, probing one
+/// random member and sweeping suspects that have timed out into `dead`.
+pub async fn run(ctx: Arc<NodeContext>) {
+    loop {
+        tokio::time::sleep(PROTOCOL_PERIOD).await;
+        probe_once(&ctx).await;
+        for u in ctx.membership.sweep_expired_suspects() {
+            println!("Membership: {} is now dead", u.device.device_id);
+            ctx.membership.push_gossip(u);
+        }
+    }
+}
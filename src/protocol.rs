@@ -0,0 +1,415 @@
+// Versioned, checksummed binary wire protocol carried inside each encrypted
+// `handshake::SecureStream` frame. Replaces the old "sniff the first three
+// bytes for GET" framing with a fixed header - magic, protocol version,
+// opcode, body length, CRC32 - followed by a typed body per opcode. A body
+// is itself `[u32 json_len][json][raw]`: the JSON part carries the typed,
+// small fields (`ShardMetadata`, ids, counts) and the raw part carries any
+// bulk bytes (shard payloads) so they never get blown up through JSON's
+// array-of-numbers encoding.
+
+use binrw::{BinRead, BinWrite};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+use crate::{ChunkInfo, Device, Result, ShardLocation, ShardMetadata};
+
+pub const PROTOCOL_VERSION: u8 = 1;
+const MAX_BODY_LEN: u32 = 64 * 1024 * 1024;
+
+#[derive(BinRead, BinWrite, Debug)]
+#[brw(big, magic = b"VWRP")]
+struct FrameHeader {
+    version: u8,
+    opcode: u8,
+    body_len: u32,
+    crc32: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Store = 1,
+    Fetch = 2,
+    Delete = 3,
+    Stat = 4,
+    List = 5,
+    FindNode = 6,
+    FindValue = 7,
+    StoreProviders = 8,
+    Ping = 9,
+    PingReq = 10,
+    MapTask = 11,
+    Reduce = 12,
+    MembershipQuery = 13,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(Opcode::Store),
+            2 => Ok(Opcode::Fetch),
+            3 => Ok(Opcode::Delete),
+            4 => Ok(Opcode::Stat),
+            5 => Ok(Opcode::List),
+            6 => Ok(Opcode::FindNode),
+            7 => Ok(Opcode::FindValue),
+            8 => Ok(Opcode::StoreProviders),
+            9 => Ok(Opcode::Ping),
+            10 => Ok(Opcode::PingReq),
+            11 => Ok(Opcode::MapTask),
+            12 => Ok(Opcode::Reduce),
+            13 => Ok(Opcode::MembershipQuery),
+            other => Err(format!("Unknown opcode {}", other).into()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ShardIdBody {
+    pub shard_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileIdBody {
+    pub file_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Ack {
+    pub ok: bool,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatResponse {
+    pub exists: bool,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListResponse {
+    pub shard_ids: Vec<String>,
+}
+
+/// One entry in a Kademlia `FIND_NODE`/`FIND_VALUE` response: a peer's
+/// routing-table id alongside how to reach it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PeerInfo {
+    pub id: [u8; 32],
+    pub device: Device,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FindNodeBody {
+    pub target: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FindNodeResponse {
+    pub peers: Vec<PeerInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FindValueBody {
+    pub key: [u8; 32],
+}
+
+/// Either the providers advertising `key`, or the closest peers known if
+/// this node doesn't have a value for it - the two outcomes `FIND_VALUE`
+/// can produce in one round trip.
+#[derive(Serialize, Deserialize)]
+pub enum FindValueResponse {
+    Value(Vec<Device>),
+    Peers(Vec<PeerInfo>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoreProvidersBody {
+    pub key: [u8; 32],
+    pub providers: Vec<Device>,
+}
+
+/// A SWIM membership fact about one device: its incarnation number and the
+/// state the sender believes it's in. Piggybacked on every `Ping`/`PingReq`
+/// and on each `Ping`'s ack (a `PingReq` is answered with a plain `Ack`) so
+/// membership state disseminates without a separate broadcast round.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum MemberState {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MemberUpdate {
+    pub device: Device,
+    pub incarnation: u64,
+    pub state: MemberState,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PingBody {
+    pub from: Device,
+    pub updates: Vec<MemberUpdate>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PingAck {
+    pub updates: Vec<MemberUpdate>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PingReqBody {
+    pub target: Device,
+    pub updates: Vec<MemberUpdate>,
+}
+
+/// Everything a worker needs to reconstruct one chunk and run a map task
+/// against it, without holding the file's full manifest - just the slice
+/// of it covering this chunk, shipped by the coordinator.
+#[derive(Serialize, Deserialize)]
+pub struct MapTaskBody {
+    pub file_id: String,
+    pub chunk_index: usize,
+    pub task: String,
+    pub encryption_key: Vec<u8>,
+    pub chunk_info: ChunkInfo,
+    pub chunk_shards: Vec<ShardLocation>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReduceBody {
+    pub pairs: Vec<(String, u64)>,
+}
+
+/// A responder's full membership table, returned for `MembershipQuery` so
+/// a short-lived CLI process can inherit a long-running daemon's
+/// SWIM-derived view by `ingest`-ing these updates, instead of starting
+/// from an empty table that's never had a protocol period to learn
+/// anything on its own.
+#[derive(Serialize, Deserialize)]
+pub struct MembershipSnapshot {
+    pub members: Vec<MemberUpdate>,
+}
+
+/// A decoded request, tagged with the opcode it travelled under and split
+/// back into its typed fields plus any trailing raw bytes.
+pub enum Request {
+    Store { metadata: ShardMetadata, shard: Vec<u8> },
+    Fetch { shard_id: String },
+    Delete { shard_id: String },
+    Stat { shard_id: String },
+    List { file_id: String },
+    FindNode { target: [u8; 32] },
+    FindValue { key: [u8; 32] },
+    StoreProviders { key: [u8; 32], providers: Vec<Device> },
+    Ping { from: Device, updates: Vec<MemberUpdate> },
+    PingReq { target: Device, updates: Vec<MemberUpdate> },
+    MapTask {
+        file_id: String,
+        chunk_index: usize,
+        task: String,
+        encryption_key: Vec<u8>,
+        chunk_info: ChunkInfo,
+        chunk_shards: Vec<ShardLocation>,
+    },
+    Reduce { pairs: Vec<(String, u64)> },
+    MembershipQuery,
+}
+
+fn crc32(body: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(body);
+    hasher.finalize()
+}
+
+fn pack_body(json: &[u8], raw: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + json.len() + raw.len());
+    body.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    body.extend_from_slice(json);
+    body.extend_from_slice(raw);
+    body
+}
+
+fn unpack_body(body: &[u8]) -> Result<(&[u8], &[u8])> {
+    if body.len() < 4 {
+        return Err("Frame body too short for length prefix".into());
+    }
+    let json_len = u32::from_be_bytes(body[0..4].try_into()?) as usize;
+    let json = body
+        .get(4..4 + json_len)
+        .ok_or("Frame body shorter than declared JSON length")?;
+    let raw = &body[4 + json_len..];
+    Ok((json, raw))
+}
+
+fn encode_frame(opcode: Opcode, json: &[u8], raw: &[u8]) -> Result<Vec<u8>> {
+    let body = pack_body(json, raw);
+    if body.len() as u64 > MAX_BODY_LEN as u64 {
+        return Err("Frame body exceeds maximum size".into());
+    }
+    let header = FrameHeader {
+        version: PROTOCOL_VERSION,
+        opcode: opcode as u8,
+        body_len: body.len() as u32,
+        crc32: crc32(&body),
+    };
+    let mut cursor = Cursor::new(Vec::new());
+    header.write(&mut cursor)?;
+    let mut out = cursor.into_inner();
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn decode_frame(data: &[u8]) -> Result<(Opcode, Vec<u8>)> {
+    let mut cursor = Cursor::new(data);
+    let header = FrameHeader::read(&mut cursor)
+        .map_err(|e| format!("Malformed frame header: {:?}", e))?;
+
+    if header.version != PROTOCOL_VERSION {
+        return Err(format!("Unsupported protocol version {}", header.version).into());
+    }
+    if header.body_len > MAX_BODY_LEN {
+        return Err("Frame body exceeds maximum size".into());
+    }
+
+    let body_start = cursor.position() as usize;
+    let body_end = body_start + header.body_len as usize;
+    let body = data
+        .get(body_start..body_end)
+        .ok_or("Frame shorter than declared body length")?
+        .to_vec();
+
+    if crc32(&body) != header.crc32 {
+        return Err("Frame failed CRC32 check".into());
+    }
+
+    Ok((Opcode::try_from(header.opcode)?, body))
+}
+
+pub fn encode_store(metadata: &ShardMetadata, shard: &[u8]) -> Result<Vec<u8>> {
+    encode_frame(Opcode::Store, &serde_json::to_vec(metadata)?, shard)
+}
+
+pub fn encode_fetch(shard_id: &str) -> Result<Vec<u8>> {
+    encode_frame(Opcode::Fetch, &serde_json::to_vec(&ShardIdBody { shard_id: shard_id.to_string() })?, &[])
+}
+
+pub fn encode_delete(shard_id: &str) -> Result<Vec<u8>> {
+    encode_frame(Opcode::Delete, &serde_json::to_vec(&ShardIdBody { shard_id: shard_id.to_string() })?, &[])
+}
+
+pub fn encode_stat(shard_id: &str) -> Result<Vec<u8>> {
+    encode_frame(Opcode::Stat, &serde_json::to_vec(&ShardIdBody { shard_id: shard_id.to_string() })?, &[])
+}
+
+pub fn encode_list(file_id: &str) -> Result<Vec<u8>> {
+    encode_frame(Opcode::List, &serde_json::to_vec(&FileIdBody { file_id: file_id.to_string() })?, &[])
+}
+
+pub fn encode_find_node(target: [u8; 32]) -> Result<Vec<u8>> {
+    encode_frame(Opcode::FindNode, &serde_json::to_vec(&FindNodeBody { target })?, &[])
+}
+
+pub fn encode_find_value(key: [u8; 32]) -> Result<Vec<u8>> {
+    encode_frame(Opcode::FindValue, &serde_json::to_vec(&FindValueBody { key })?, &[])
+}
+
+pub fn encode_store_providers(key: [u8; 32], providers: Vec<Device>) -> Result<Vec<u8>> {
+    encode_frame(Opcode::StoreProviders, &serde_json::to_vec(&StoreProvidersBody { key, providers })?, &[])
+}
+
+pub fn encode_ping(from: Device, updates: Vec<MemberUpdate>) -> Result<Vec<u8>> {
+    encode_frame(Opcode::Ping, &serde_json::to_vec(&PingBody { from, updates })?, &[])
+}
+
+pub fn encode_ping_req(target: Device, updates: Vec<MemberUpdate>) -> Result<Vec<u8>> {
+    encode_frame(Opcode::PingReq, &serde_json::to_vec(&PingReqBody { target, updates })?, &[])
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn encode_map_task(
+    file_id: &str,
+    chunk_index: usize,
+    task: &str,
+    encryption_key: &[u8],
+    chunk_info: ChunkInfo,
+    chunk_shards: Vec<ShardLocation>,
+) -> Result<Vec<u8>> {
+    let body = MapTaskBody {
+        file_id: file_id.to_string(),
+        chunk_index,
+        task: task.to_string(),
+        encryption_key: encryption_key.to_vec(),
+        chunk_info,
+        chunk_shards,
+    };
+    encode_frame(Opcode::MapTask, &serde_json::to_vec(&body)?, &[])
+}
+
+pub fn encode_reduce(pairs: Vec<(String, u64)>) -> Result<Vec<u8>> {
+    encode_frame(Opcode::Reduce, &serde_json::to_vec(&ReduceBody { pairs })?, &[])
+}
+
+pub fn encode_membership_query() -> Result<Vec<u8>> {
+    encode_frame(Opcode::MembershipQuery, b"{}", &[])
+}
+
+/// Parses a request frame's body into the `Request` matching its opcode.
+pub fn decode_request(data: &[u8]) -> Result<Request> {
+    let (opcode, body) = decode_frame(data)?;
+    let (json, raw) = unpack_body(&body)?;
+
+    Ok(match opcode {
+        Opcode::Store => Request::Store { metadata: serde_json::from_slice(json)?, shard: raw.to_vec() },
+        Opcode::Fetch => Request::Fetch { shard_id: serde_json::from_slice::<ShardIdBody>(json)?.shard_id },
+        Opcode::Delete => Request::Delete { shard_id: serde_json::from_slice::<ShardIdBody>(json)?.shard_id },
+        Opcode::Stat => Request::Stat { shard_id: serde_json::from_slice::<ShardIdBody>(json)?.shard_id },
+        Opcode::List => Request::List { file_id: serde_json::from_slice::<FileIdBody>(json)?.file_id },
+        Opcode::FindNode => Request::FindNode { target: serde_json::from_slice::<FindNodeBody>(json)?.target },
+        Opcode::FindValue => Request::FindValue { key: serde_json::from_slice::<FindValueBody>(json)?.key },
+        Opcode::StoreProviders => {
+            let body: StoreProvidersBody = serde_json::from_slice(json)?;
+            Request::StoreProviders { key: body.key, providers: body.providers }
+        }
+        Opcode::Ping => {
+            let body: PingBody = serde_json::from_slice(json)?;
+            Request::Ping { from: body.from, updates: body.updates }
+        }
+        Opcode::PingReq => {
+            let body: PingReqBody = serde_json::from_slice(json)?;
+            Request::PingReq { target: body.target, updates: body.updates }
+        }
+        Opcode::MapTask => {
+            let body: MapTaskBody = serde_json::from_slice(json)?;
+            Request::MapTask {
+                file_id: body.file_id,
+                chunk_index: body.chunk_index,
+                task: body.task,
+                encryption_key: body.encryption_key,
+                chunk_info: body.chunk_info,
+                chunk_shards: body.chunk_shards,
+            }
+        }
+        Opcode::Reduce => {
+            let body: ReduceBody = serde_json::from_slice(json)?;
+            Request::Reduce { pairs: body.pairs }
+        }
+        Opcode::MembershipQuery => Request::MembershipQuery,
+    })
+}
+
+/// Encodes a response carrying a JSON-serializable value plus optional raw
+/// trailing bytes (shard data for a `Fetch` response, JSON-encoded key/count
+/// pairs for a `MapTask` or `Reduce` response; empty otherwise).
+pub fn encode_response<T: Serialize>(opcode: Opcode, value: &T, raw: &[u8]) -> Result<Vec<u8>> {
+    encode_frame(opcode, &serde_json::to_vec(value)?, raw)
+}
+
+/// Decodes a response frame back into its JSON value and any raw bytes.
+pub fn decode_response<T: for<'de> Deserialize<'de>>(data: &[u8]) -> Result<(T, Vec<u8>)> {
+    let (_, body) = decode_frame(data)?;
+    let (json, raw) = unpack_body(&body)?;
+    Ok((serde_json::from_slice(json)?, raw.to_vec()))
+}
@@ -0,0 +1,47 @@
+// Ed25519 manifest signing. An uploader signs the digests it computed for a
+// manifest so tampering with the manifest (not just a shard) is detectable,
+// and any device that has independently checked those digests can
+// cross-sign the same manifest - growing a small, locally-verifiable set of
+// attestations `verify` can report on before a caller commits to pulling
+// down potentially large chunks.
+
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+
+use crate::Result;
+
+/// Loads this device's persistent Ed25519 signing key, generating and
+/// saving one on first use - one file per device id, mirroring
+/// `handshake::StaticIdentity`'s per-port key file.
+pub fn load_or_generate(device_id: &str) -> Result<SigningKey> {
+    let key_file = format!("signing_{}.hex", device_id);
+
+    if Path::new(&key_file).exists() {
+        let hex_str = fs::read_to_string(&key_file)?;
+        let bytes = hex::decode(hex_str.trim())?;
+        let bytes: [u8; 32] = bytes.as_slice().try_into().map_err(|_| "Invalid signing key file")?;
+        return Ok(SigningKey::from_bytes(&bytes));
+    }
+
+    let signing_key = SigningKey::generate(&mut OsRng);
+    fs::write(&key_file, hex::encode(signing_key.to_bytes()))?;
+    Ok(signing_key)
+}
+
+pub fn sign(key: &SigningKey, message: &[u8]) -> Vec<u8> {
+    key.sign(message).to_bytes().to_vec()
+}
+
+/// Verifies `signature` over `message` under the given 32-byte Ed25519
+/// public key. Returns `false` (rather than an error) for malformed input,
+/// since a caller only ever wants a yes/no attestation result.
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(key_bytes) = <[u8; 32]>::try_from(public_key) else { return false };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&key_bytes) else { return false };
+    let Ok(sig_bytes) = <[u8; 64]>::try_from(signature) else { return false };
+    let signature = Signature::from_bytes(&sig_bytes);
+    verifying_key.verify(message, &signature).is_ok()
+}
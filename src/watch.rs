@@ -0,0 +1,88 @@
+// Auto-sync daemon: watches a directory with a cross-platform filesystem
+// notification backend and feeds changed files through the same
+// chunking/manifest/upload pipeline `upload` uses by hand, so files dropped
+// into a watched folder get replicated and discovered exactly like manually
+// uploaded ones. Bursts of writes to the same path (editors often save in
+// several steps) are coalesced by only acting on a path once it's gone
+// quiet for `DEBOUNCE`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{NodeContext, Result};
+
+const DEBOUNCE: Duration = Duration::from_millis(800);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Runs the watch daemon forever: observes `dir`, debounces per-path
+/// events, and uploads (or tombstones) whatever settles.
+pub async fn run(dir: &str, ctx: Arc<NodeContext>) -> Result<()> {
+    let (tx, rx) = std_mpsc::channel();
+    let mut watcher: RecommendedWatcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                let _ = tx.send(event);
+            }
+        })?;
+    watcher.watch(Path::new(dir), RecursiveMode::Recursive)?;
+
+    println!("Watching {} for changes...", dir);
+
+    // Pending[path] = (last event time, was it a removal).
+    let mut pending: HashMap<PathBuf, (Instant, bool)> = HashMap::new();
+
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(event) => {
+                let is_delete = matches!(event.kind, EventKind::Remove(_));
+                for path in event.paths {
+                    if path.is_dir() {
+                        continue;
+                    }
+                    pending.insert(path, (Instant::now(), is_delete));
+                }
+            }
+            Err(std_mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, (last_seen, _))| last_seen.elapsed() >= DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in settled {
+            let (_, was_delete) = pending.remove(&path).unwrap();
+            sync_path(&path, was_delete, Arc::clone(&ctx)).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn sync_path(path: &Path, was_delete: bool, ctx: Arc<NodeContext>) {
+    let Some(path_str) = path.to_str() else {
+        eprintln!("Skipping non-UTF-8 path: {:?}", path);
+        return;
+    };
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or(path_str);
+
+    if was_delete || !path.exists() {
+        println!("Detected removal: {}", path_str);
+        if let Err(e) = crate::upload_tombstone(name, ctx).await {
+            eprintln!("Tombstone failed for {}: {}", path_str, e);
+        }
+        return;
+    }
+
+    println!("Detected change: {}", path_str);
+    if let Err(e) = crate::upload(path_str, ctx).await {
+        eprintln!("Auto-upload failed for {}: {}", path_str, e);
+    }
+}